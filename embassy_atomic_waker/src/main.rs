@@ -1,57 +1,15 @@
 #![no_std]
 #![no_main]
 
-use core::cell::Cell;
-use core::future::poll_fn;
-use core::task::Poll;
 use defmt::*;
 use embassy_executor::Spawner;
-use embassy_sync::blocking_mutex::ThreadModeMutex;
 use embassy_time::Timer;
 use static_cell::StaticCell;
+use waker_churn::{ChangeSignal, State};
 
 use {defmt_rtt as _, panic_probe as _};
 
-#[derive(Debug, Clone, Copy, Default, PartialEq, defmt::Format)]
-enum State {
-    #[default]
-    NotReady,
-    Ready(u32),
-}
-
-struct Signal {
-    state: Cell<State>,
-    waker_registration: embassy_sync::waitqueue::AtomicWaker,
-}
-
-impl Signal {
-    fn new() -> Self {
-        Self {
-            state: Cell::new(State::NotReady),
-            waker_registration: embassy_sync::waitqueue::AtomicWaker::new(),
-        }
-    }
-}
-
-type SyncSignal = ThreadModeMutex<Signal>;
-
-async fn signal_wait(signal: &SyncSignal, current_state: State) {
-    let mut counter = 0;
-
-    poll_fn(move |cx| {
-        signal.lock(|s| {
-            if s.state.get() != current_state {
-                info!("Signal is ready. Number of polls: {}", counter);
-                Poll::Ready(())
-            } else {
-                counter += 1;
-                s.waker_registration.register(cx.waker());
-                Poll::Pending
-            }
-        })
-    })
-    .await;
-}
+type SyncSignal = waker_churn::AtomicWakerSignal;
 
 /// The `AtomicWaker` is intentionally not designed for waking multiple tasks, but rather for waking a task from an interrupt.
 /// As visible in this example, only one task will be woken up repeatedly.
@@ -61,7 +19,7 @@ async fn main(spawner: Spawner) {
     info!("Hello World!");
 
     static SIGNAL: StaticCell<SyncSignal> = StaticCell::new();
-    let signal = SIGNAL.init(ThreadModeMutex::new(Signal::new()));
+    let signal = SIGNAL.init(SyncSignal::new());
     let mut counter = 0;
 
     spawner.must_spawn(wait_for_signal("TaskTwo", signal, true));
@@ -71,30 +29,11 @@ async fn main(spawner: Spawner) {
         Timer::after_millis(500).await;
         counter += 1;
 
-        signal.lock(|s| {
-            s.state.set(State::Ready(counter));
-            s.waker_registration.wake();
-        });
+        signal.set(State::Ready(counter));
     }
 }
 
 #[embassy_executor::task(pool_size = 2)]
 async fn wait_for_signal(name: &'static str, signal: &'static SyncSignal, odd: bool) {
-    info!("Starting {} task", name);
-
-    loop {
-        let current_state = signal.lock(|s| s.state.get());
-
-        match (odd, current_state) {
-            (true, State::Ready(x)) if x % 2 == 1 => {
-                info!("{}: Odd state: {:?}", name, current_state);
-            }
-            (false, State::Ready(x)) if x % 2 == 0 => {
-                info!("{}: Even state: {:?}", name, current_state);
-            }
-            _ => {}
-        }
-
-        signal_wait(signal, current_state).await;
-    }
+    waker_churn::wait_for_signal(name, signal, odd).await;
 }
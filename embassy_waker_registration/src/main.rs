@@ -1,66 +1,35 @@
 #![no_std]
 #![no_main]
 
-use core::cell::{Cell, RefCell};
-use core::future::poll_fn;
-use core::task::Poll;
 use defmt::*;
 use embassy_executor::Spawner;
-use embassy_sync::blocking_mutex::ThreadModeMutex;
+use embassy_sync::waitqueue::WakerRegistration;
 use embassy_time::Timer;
 use static_cell::StaticCell;
+use waker_churn::ping_pong::PingPongDetector;
+use waker_churn::stats::ChurnStats;
+use waker_churn::{ChangeSignal, State};
 
 use {defmt_rtt as _, panic_probe as _};
 
-#[derive(Debug, Clone, Copy, Default, PartialEq, defmt::Format)]
-enum State {
-    #[default]
-    NotReady,
-    Ready(u32),
-}
-
-struct Signal {
-    state: Cell<State>,
-    waker_registration: RefCell<embassy_sync::waitqueue::WakerRegistration>,
-}
-
-impl Signal {
-    fn new() -> Self {
-        Self {
-            state: Cell::new(State::NotReady),
-            waker_registration: RefCell::new(embassy_sync::waitqueue::WakerRegistration::new()),
-        }
-    }
-}
+/// `WakerRegistration` which warns once the two tasks take turns for 8 registrations in a row.
+type SyncSignal =
+    waker_churn::SingleSlotSignal<PingPongDetector<WakerRegistration, 8>, &'static ChurnStats<2>>;
 
-type SyncSignal = ThreadModeMutex<Signal>;
+/// Counts the churn of the two tasks. The summary is logged every 10 updates.
+static STATS: ChurnStats<2> = ChurnStats::new();
 
-async fn signal_wait(signal: &SyncSignal, current_state: State) {
-    let mut counter = 0;
-
-    poll_fn(move |cx| {
-        signal.lock(|s| {
-            if s.state.get() != current_state {
-                info!("Signal is ready. Number of polls: {}", counter);
-                Poll::Ready(())
-            } else {
-                counter += 1;
-                s.waker_registration.borrow_mut().register(cx.waker());
-                Poll::Pending
-            }
-        })
-    })
-    .await;
-}
-
-/// This version gets stuck in `signal_wait` jumping between the two tasks with `.wake()`
+/// This version gets stuck in `wait_changed` jumping between the two tasks with `.wake()`
 #[embassy_executor::main]
 async fn main(spawner: Spawner) {
     let _p = embassy_stm32::init(Default::default());
     info!("Hello World!");
 
     static SIGNAL: StaticCell<SyncSignal> = StaticCell::new();
-    let signal = SIGNAL.init(ThreadModeMutex::new(Signal::new()));
+    let signal = SIGNAL.init(SyncSignal::from_slot(
+        PingPongDetector::new(WakerRegistration::new()),
+        &STATS,
+    ));
     let mut counter = 0;
 
     spawner.must_spawn(wait_for_signal("TaskTwo", signal, true));
@@ -70,30 +39,19 @@ async fn main(spawner: Spawner) {
         Timer::after_millis(500).await;
         counter += 1;
 
-        signal.lock(|s| {
-            s.state.set(State::Ready(counter));
-            s.waker_registration.borrow_mut().wake();
-        });
+        signal.set(State::Ready(counter));
+
+        if counter % 10 == 0 {
+            STATS.log_summary();
+            info!(
+                "Ping-pongs detected: {}",
+                signal.slot(|detector| detector.count())
+            );
+        }
     }
 }
 
 #[embassy_executor::task(pool_size = 2)]
 async fn wait_for_signal(name: &'static str, signal: &'static SyncSignal, odd: bool) {
-    info!("Starting {} task", name);
-
-    loop {
-        let current_state = signal.lock(|s| s.state.get());
-
-        match (odd, current_state) {
-            (true, State::Ready(x)) if x % 2 == 1 => {
-                info!("{}: Odd state: {:?}", name, current_state);
-            }
-            (false, State::Ready(x)) if x % 2 == 0 => {
-                info!("{}: Even state: {:?}", name, current_state);
-            }
-            _ => {}
-        }
-
-        signal_wait(signal, current_state).await;
-    }
+    waker_churn::wait_for_signal(name, signal, odd).await;
 }
@@ -0,0 +1,48 @@
+//! Compare all waker strategies on the simulator and export the numbers.
+//!
+//! Usage: `cargo run --release --bin churn_report [OUT_DIR]`
+//! Writes `churn_report.json` and `churn_report.csv` to `OUT_DIR` (default: the current directory),
+//! and the comparison of the broadcast channels to `broadcast_report.json` and `broadcast_report.csv`.
+
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::PathBuf;
+
+use waker_churn::report::{self, BroadcastRow, Row};
+
+fn main() -> std::io::Result<()> {
+    let out_dir = PathBuf::from(std::env::args().nth(1).unwrap_or_else(|| ".".into()));
+    std::fs::create_dir_all(&out_dir)?;
+
+    // The main thread is named `main`, so the `ThreadModeMutex` of the strategies can be used here
+    let rows: Vec<Row> = report::matrix().map(report::run).collect();
+
+    let json = out_dir.join("churn_report.json");
+    report::write_json(&rows, BufWriter::new(File::create(&json)?))?;
+    let csv = out_dir.join("churn_report.csv");
+    report::write_csv(&rows, BufWriter::new(File::create(&csv)?))?;
+
+    println!(
+        "{} scenarios written to {} and {}",
+        rows.len(),
+        json.display(),
+        csv.display()
+    );
+
+    let rows: Vec<BroadcastRow> = report::broadcast_matrix()
+        .map(report::run_broadcast)
+        .collect();
+
+    let json = out_dir.join("broadcast_report.json");
+    report::write_broadcast_json(&rows, BufWriter::new(File::create(&json)?))?;
+    let csv = out_dir.join("broadcast_report.csv");
+    report::write_broadcast_csv(&rows, BufWriter::new(File::create(&csv)?))?;
+
+    println!(
+        "{} broadcast scenarios written to {} and {}",
+        rows.len(),
+        json.display(),
+        csv.display()
+    );
+    Ok(())
+}
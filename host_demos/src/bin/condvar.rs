@@ -0,0 +1,57 @@
+//! The `maitake_wait_queue` demo with the state in a blocking mutex and a `Condvar` instead of a signal.
+//!
+//! The button of the board is replaced by a thread which changes the state whenever Enter is pressed.
+
+use core::cell::Cell;
+use std::sync::atomic::{AtomicU32, Ordering};
+
+use embassy_executor::Spawner;
+use embassy_sync::blocking_mutex::Mutex;
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_time::Timer;
+use log::*;
+use waker_churn::{Condvar, State};
+
+/// `CriticalSectionRawMutex`, so the button can change the state from another context.
+/// `wait_while` reads the new state under the same lock it checked the condition with.
+static STATE: Mutex<CriticalSectionRawMutex, Cell<State>> = Mutex::new(Cell::new(State::NotReady));
+static CONDVAR: Condvar = Condvar::new();
+
+/// Shared by the timer and the button
+static COUNTER: AtomicU32 = AtomicU32::new(0);
+
+#[embassy_executor::main]
+async fn main(spawner: Spawner) {
+    host_demos::init_logger();
+    info!("Hello World!");
+
+    spawner.must_spawn(wait_for_condvar("TaskTwo", true));
+    spawner.must_spawn(wait_for_condvar("TaskOne", false));
+    spawner.must_spawn(wait_for_condvar("TaskThree", true));
+
+    std::thread::spawn(publish_on_button);
+
+    loop {
+        Timer::after_millis(500).await;
+        let counter = COUNTER.fetch_add(1, Ordering::Relaxed) + 1;
+
+        STATE.lock(|state| state.set(State::Ready(counter)));
+        CONDVAR.notify_all();
+    }
+}
+
+#[embassy_executor::task(pool_size = 3)]
+async fn wait_for_condvar(name: &'static str, odd: bool) {
+    waker_churn::wait_for_condvar(name, &STATE, &CONDVAR, odd).await;
+}
+
+/// Changes the state on every line of stdin, like the EXTI button handler on the board.
+fn publish_on_button() {
+    for _ in std::io::stdin().lines() {
+        let counter = COUNTER.fetch_add(1, Ordering::Relaxed) + 1;
+        info!("Button pressed, publishing {}", counter);
+
+        STATE.lock(|state| state.set(State::Ready(counter)));
+        CONDVAR.notify_all();
+    }
+}
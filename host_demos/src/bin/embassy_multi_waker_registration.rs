@@ -0,0 +1,35 @@
+//! Host build of the `embassy_multi_waker_registration` demo.
+
+use embassy_executor::Spawner;
+use embassy_time::Timer;
+use log::*;
+use static_cell::StaticCell;
+use waker_churn::{ChangeSignal, State};
+
+type SyncSignal = waker_churn::MultiWakerRegistrationSignal<2>;
+
+/// This __does__ work!
+#[embassy_executor::main]
+async fn main(spawner: Spawner) {
+    host_demos::init_logger();
+    info!("Hello World!");
+
+    static SIGNAL: StaticCell<SyncSignal> = StaticCell::new();
+    let signal = SIGNAL.init(SyncSignal::new());
+    let mut counter = 0;
+
+    spawner.must_spawn(wait_for_signal("TaskTwo", signal, true));
+    spawner.must_spawn(wait_for_signal("TaskOne", signal, false));
+
+    loop {
+        Timer::after_millis(500).await;
+        counter += 1;
+
+        signal.set(State::Ready(counter));
+    }
+}
+
+#[embassy_executor::task(pool_size = 2)]
+async fn wait_for_signal(name: &'static str, signal: &'static SyncSignal, odd: bool) {
+    waker_churn::wait_for_signal(name, signal, odd).await;
+}
@@ -0,0 +1,54 @@
+//! Host build of the `embassy_waker_registration` demo.
+
+use embassy_executor::Spawner;
+use embassy_sync::waitqueue::WakerRegistration;
+use embassy_time::Timer;
+use log::*;
+use static_cell::StaticCell;
+use waker_churn::ping_pong::PingPongDetector;
+use waker_churn::stats::ChurnStats;
+use waker_churn::{ChangeSignal, State};
+
+/// `WakerRegistration` which warns once the two tasks take turns for 8 registrations in a row.
+type SyncSignal =
+    waker_churn::SingleSlotSignal<PingPongDetector<WakerRegistration, 8>, &'static ChurnStats<2>>;
+
+/// Counts the churn of the two tasks. The summary is logged every 10 updates.
+static STATS: ChurnStats<2> = ChurnStats::new();
+
+/// This version gets stuck in `wait_changed` jumping between the two tasks with `.wake()`
+#[embassy_executor::main]
+async fn main(spawner: Spawner) {
+    host_demos::init_logger();
+    info!("Hello World!");
+
+    static SIGNAL: StaticCell<SyncSignal> = StaticCell::new();
+    let signal = SIGNAL.init(SyncSignal::from_slot(
+        PingPongDetector::new(WakerRegistration::new()),
+        &STATS,
+    ));
+    let mut counter = 0;
+
+    spawner.must_spawn(wait_for_signal("TaskTwo", signal, true));
+    spawner.must_spawn(wait_for_signal("TaskOne", signal, false));
+
+    loop {
+        Timer::after_millis(500).await;
+        counter += 1;
+
+        signal.set(State::Ready(counter));
+
+        if counter % 10 == 0 {
+            STATS.log_summary();
+            info!(
+                "Ping-pongs detected: {}",
+                signal.slot(|detector| detector.count())
+            );
+        }
+    }
+}
+
+#[embassy_executor::task(pool_size = 2)]
+async fn wait_for_signal(name: &'static str, signal: &'static SyncSignal, odd: bool) {
+    waker_churn::wait_for_signal(name, signal, odd).await;
+}
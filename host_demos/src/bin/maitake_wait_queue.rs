@@ -0,0 +1,56 @@
+//! Host build of the `maitake_wait_queue` demo.
+//!
+//! The button of the board is replaced by a thread which publishes a new state whenever Enter is pressed.
+
+use std::sync::atomic::{AtomicU32, Ordering};
+
+use embassy_executor::Spawner;
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_time::Timer;
+use log::*;
+use static_cell::StaticCell;
+use waker_churn::{ChangeSignal, State};
+
+/// `CriticalSectionRawMutex` instead of the default `ThreadModeRawMutex`, so the button can `set` from another context.
+type SyncSignal = waker_churn::WaitQueueSignal<(), CriticalSectionRawMutex>;
+
+/// Shared by the timer and the button
+static COUNTER: AtomicU32 = AtomicU32::new(0);
+
+/// This __does__ work! And better, no manual "leaf" future implementation via `poll_fn` is needed. Also the number of waiters does not need to be specified upfront.
+#[embassy_executor::main]
+async fn main(spawner: Spawner) {
+    host_demos::init_logger();
+    info!("Hello World!");
+
+    static SIGNAL: StaticCell<SyncSignal> = StaticCell::new();
+    let signal: &'static SyncSignal = SIGNAL.init(SyncSignal::new());
+
+    spawner.must_spawn(wait_for_signal("TaskTwo", signal, true));
+    spawner.must_spawn(wait_for_signal("TaskOne", signal, false));
+    spawner.must_spawn(wait_for_signal("TaskThree", signal, true));
+
+    std::thread::spawn(move || publish_on_button(signal));
+
+    loop {
+        Timer::after_millis(500).await;
+        let counter = COUNTER.fetch_add(1, Ordering::Relaxed) + 1;
+
+        signal.set(State::Ready(counter));
+    }
+}
+
+#[embassy_executor::task(pool_size = 3)]
+async fn wait_for_signal(name: &'static str, signal: &'static SyncSignal, odd: bool) {
+    waker_churn::wait_for_signal(name, signal, odd).await;
+}
+
+/// Publishes a new state on every line of stdin, like the EXTI button handler on the board.
+fn publish_on_button(signal: &'static SyncSignal) {
+    for _ in std::io::stdin().lines() {
+        let counter = COUNTER.fetch_add(1, Ordering::Relaxed) + 1;
+        info!("Button pressed, publishing {}", counter);
+
+        signal.set(State::Ready(counter));
+    }
+}
@@ -0,0 +1,172 @@
+//! Host build of the `maitake_wait_queue_peripheral` demo.
+//! The GPIO output is replaced by a mock which logs its level whenever it is created, toggled or dropped.
+
+use embassy_executor::Spawner;
+use embassy_time::WithTimeout;
+use log::*;
+use static_cell::StaticCell;
+use waker_churn::names::register_current_task;
+use waker_churn::on_demand::OutputFactory;
+
+/// Stand-in for `embassy_stm32::gpio::Output`.
+struct MockOutput {
+    /// Name of the pin
+    pin: &'static str,
+    /// Current output level
+    high: bool,
+}
+
+impl MockOutput {
+    fn toggle(&mut self) {
+        self.high = !self.high;
+        trace!("{}: Toggled output, high: {}", self.pin, self.high);
+    }
+}
+
+impl Drop for MockOutput {
+    fn drop(&mut self) {
+        debug!("{}: Output dropped", self.pin);
+    }
+}
+
+/// Configuration of the mock output pin.
+struct OutputConfig {
+    /// Name of the pin
+    pin: &'static str,
+    /// Initial output level
+    high: bool,
+}
+
+impl OutputFactory for OutputConfig {
+    type Output = MockOutput;
+
+    fn create(&self) -> Self::Output {
+        debug!("{}: Output created, high: {}", self.pin, self.high);
+        MockOutput {
+            pin: self.pin,
+            high: self.high,
+        }
+    }
+}
+
+type OnDemandOutput = waker_churn::on_demand::OnDemandOutput<OutputConfig>;
+
+#[embassy_executor::main]
+async fn main(spawner: Spawner) {
+    host_demos::init_logger();
+    info!("Hello World!");
+
+    static CELL: StaticCell<OnDemandOutput> = StaticCell::new();
+
+    let on_demand = CELL.init(OnDemandOutput::new(OutputConfig {
+        pin: "PD0",
+        high: false,
+    }));
+
+    let starting_instant = embassy_time::Instant::from_ticks(0);
+
+    // Start tasks which absolute time reference
+    spawner.must_spawn(blink_fast("TaskOne", on_demand, starting_instant));
+    spawner.must_spawn(blink_slow("TaskTwo", on_demand, starting_instant));
+    spawner.must_spawn(blink_fast_cancel("TaskCancel", on_demand, starting_instant));
+}
+
+/// Blink slow
+#[embassy_executor::task]
+async fn blink_slow(
+    name: &'static str,
+    output: &'static OnDemandOutput,
+    mut instant: embassy_time::Instant,
+) {
+    let _name = register_current_task(name).await;
+
+    loop {
+        info!(
+            "Starting {} at {}: Waiting for output",
+            name,
+            instant.as_millis()
+        );
+
+        {
+            let mut guard = output.get_or_init().await;
+            info!("{}: Got output - Toggle", name);
+
+            // Use the output
+            for _ in 0..=3 {
+                guard.toggle();
+                embassy_time::Timer::after(embassy_time::Duration::from_millis(1000)).await;
+            }
+        }
+
+        let new_instant = instant + embassy_time::Duration::from_millis(10000);
+        embassy_time::Timer::at(new_instant).await;
+        instant = new_instant;
+    }
+}
+
+/// Blink fast
+#[embassy_executor::task]
+async fn blink_fast(
+    name: &'static str,
+    output: &'static OnDemandOutput,
+    mut instant: embassy_time::Instant,
+) {
+    let _name = register_current_task(name).await;
+
+    loop {
+        info!(
+            "Starting {} at {}: Waiting for output",
+            name,
+            instant.as_millis()
+        );
+
+        {
+            let mut guard = output.get_or_init().await;
+            info!("{}: Got output - Toggle", name);
+
+            // Use the output
+            for _ in 0..=3 {
+                guard.toggle();
+                embassy_time::Timer::after(embassy_time::Duration::from_millis(250)).await;
+            }
+        }
+
+        let new_instant = instant + embassy_time::Duration::from_millis(10000);
+        embassy_time::Timer::at(new_instant).await;
+        instant = new_instant;
+    }
+}
+
+/// Task which tests safe cancellation of the `get_or_init` method.
+#[embassy_executor::task]
+async fn blink_fast_cancel(
+    name: &'static str,
+    output: &'static OnDemandOutput,
+    mut instant: embassy_time::Instant,
+) {
+    let _name = register_current_task(name).await;
+
+    loop {
+        info!(
+            "Starting {} at {}: Waiting for output",
+            name,
+            instant.as_millis()
+        );
+
+        let guard_res = output
+            .get_or_init()
+            .with_timeout(embassy_time::Duration::from_millis(20))
+            .await;
+
+        match guard_res {
+            Ok(_) => {}
+            Err(_) => {
+                info!("{}: Cancelled", name);
+            }
+        }
+
+        let new_instant = instant + embassy_time::Duration::from_millis(10000);
+        embassy_time::Timer::at(new_instant).await;
+        instant = new_instant;
+    }
+}
@@ -0,0 +1,40 @@
+//! Host build of the `naive_waker` demo.
+
+use core::task::Waker;
+
+use embassy_executor::Spawner;
+use embassy_time::Timer;
+use log::*;
+use static_cell::StaticCell;
+use waker_churn::lost_wakeup::LostWakeupDetector;
+use waker_churn::{ChangeSignal, State};
+
+/// The naive signal, but every waker which gets overwritten before it was woken is reported as a lost wakeup.
+type SyncSignal = waker_churn::SingleSlotSignal<LostWakeupDetector<Option<Waker>, 4>>;
+
+/// Problem here is that there is only one waker spot in the signal struct. An if the waker gets replaced, the original waker will not be called.
+/// Therefore, only one task will be woken up.
+#[embassy_executor::main]
+async fn main(spawner: Spawner) {
+    host_demos::init_logger();
+    info!("Hello World!");
+
+    static SIGNAL: StaticCell<SyncSignal> = StaticCell::new();
+    let signal = SIGNAL.init(SyncSignal::from_slot(LostWakeupDetector::new(None), ()));
+    let mut counter = 0;
+
+    spawner.must_spawn(wait_for_signal("TaskTwo", signal, true));
+    spawner.must_spawn(wait_for_signal("TaskOne", signal, false));
+
+    loop {
+        Timer::after_millis(500).await;
+        counter += 1;
+
+        signal.set(State::Ready(counter));
+    }
+}
+
+#[embassy_executor::task(pool_size = 2)]
+async fn wait_for_signal(name: &'static str, signal: &'static SyncSignal, odd: bool) {
+    waker_churn::wait_for_signal(name, signal, odd).await;
+}
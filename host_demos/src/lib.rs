@@ -0,0 +1,9 @@
+//! Helpers shared by the host demo binaries.
+
+/// Initialize the logger with the same log level as `DEFMT_LOG` of the embedded demos.
+pub fn init_logger() {
+    env_logger::builder()
+        .filter_level(log::LevelFilter::Trace)
+        .format_timestamp_millis()
+        .init();
+}
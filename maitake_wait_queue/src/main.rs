@@ -1,89 +1,75 @@
 #![no_std]
 #![no_main]
 
-use core::cell::Cell;
+use core::sync::atomic::{AtomicU32, Ordering};
+
 use defmt::*;
-use embassy_executor::Spawner;
-use embassy_sync::blocking_mutex::ThreadModeMutex;
+use embassy_executor::{InterruptExecutor, Spawner};
+use embassy_stm32::exti::ExtiInput;
+use embassy_stm32::gpio::Pull;
+use embassy_stm32::interrupt;
+use embassy_stm32::interrupt::{InterruptExt, Priority};
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
 use embassy_time::Timer;
-use maitake_sync::WaitQueue;
 use static_cell::StaticCell;
+use waker_churn::{ChangeSignal, State};
 
 use {defmt_rtt as _, panic_probe as _};
 
-#[derive(Debug, Clone, Copy, Default, PartialEq, defmt::Format)]
-enum State {
-    #[default]
-    NotReady,
-    Ready(u32),
-}
+/// `CriticalSectionRawMutex` instead of the default `ThreadModeRawMutex`, so the button can `set` from an interrupt.
+type SyncSignal = waker_churn::WaitQueueSignal<(), CriticalSectionRawMutex>;
 
-struct Signal {
-    state: ThreadModeMutex<Cell<State>>,
-    waker_registration: WaitQueue,
-}
+/// Shared by the timer in thread mode and the button in interrupt mode
+static COUNTER: AtomicU32 = AtomicU32::new(0);
 
-impl Signal {
-    fn new() -> Self {
-        Self {
-            state: ThreadModeMutex::new(Cell::new(State::NotReady)),
-            waker_registration: WaitQueue::new(),
-        }
-    }
-}
+/// Runs the button task in the `SAI1` interrupt (unused otherwise), at a higher priority than the thread mode tasks.
+static EXECUTOR_BUTTON: InterruptExecutor = InterruptExecutor::new();
 
-type SyncSignal = Signal;
-
-async fn signal_wait(signal: &SyncSignal, current_state: State) {
-    signal
-        .waker_registration
-        .wait_for(|| current_state != signal.state.lock(|s| s.get()))
-        .await
-        .expect("Failed to wait");
+#[interrupt]
+unsafe fn SAI1() {
+    unsafe { EXECUTOR_BUTTON.on_interrupt() }
 }
 
 /// This __does__ work! And better, no manual "leaf" future implementation via `poll_fn` is needed. Also the number of waiters does not need to be specified upfront.
 #[embassy_executor::main]
 async fn main(spawner: Spawner) {
-    let _p = embassy_stm32::init(Default::default());
+    let p = embassy_stm32::init(Default::default());
     info!("Hello World!");
 
     static SIGNAL: StaticCell<SyncSignal> = StaticCell::new();
-    let signal = SIGNAL.init(Signal::new());
-    let mut counter = 0;
+    let signal = SIGNAL.init(SyncSignal::new());
 
     spawner.must_spawn(wait_for_signal("TaskTwo", signal, true));
     spawner.must_spawn(wait_for_signal("TaskOne", signal, false));
     spawner.must_spawn(wait_for_signal("TaskThree", signal, true));
 
+    // SW1 of the Nucleo board
+    let button = ExtiInput::new(p.PC4, p.EXTI4, Pull::Up);
+    interrupt::SAI1.set_priority(Priority::P6);
+    let button_spawner = EXECUTOR_BUTTON.start(interrupt::SAI1);
+    button_spawner.must_spawn(publish_on_button(button, signal));
+
     loop {
         Timer::after_millis(500).await;
-        counter += 1;
+        let counter = COUNTER.fetch_add(1, Ordering::Relaxed) + 1;
 
-        signal.state.lock(|s| {
-            s.set(State::Ready(counter));
-        });
-        signal.waker_registration.wake_all();
+        signal.set(State::Ready(counter));
     }
 }
 
 #[embassy_executor::task(pool_size = 3)]
 async fn wait_for_signal(name: &'static str, signal: &'static SyncSignal, odd: bool) {
-    info!("Starting {} task", name);
+    waker_churn::wait_for_signal(name, signal, odd).await;
+}
 
+/// Publishes a new state on every press of the button. Runs in interrupt mode and wakes the thread mode waiters.
+#[embassy_executor::task]
+async fn publish_on_button(mut button: ExtiInput<'static>, signal: &'static SyncSignal) {
     loop {
-        let current_state = signal.state.lock(|s| s.get());
-
-        match (odd, current_state) {
-            (true, State::Ready(x)) if x % 2 == 1 => {
-                info!("{}: Odd state: {:?}", name, current_state);
-            }
-            (false, State::Ready(x)) if x % 2 == 0 => {
-                info!("{}: Even state: {:?}", name, current_state);
-            }
-            _ => {}
-        }
-
-        signal_wait(signal, current_state).await;
+        button.wait_for_falling_edge().await;
+        let counter = COUNTER.fetch_add(1, Ordering::Relaxed) + 1;
+        info!("Button pressed, publishing {}", counter);
+
+        signal.set(State::Ready(counter));
     }
 }
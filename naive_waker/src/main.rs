@@ -1,48 +1,18 @@
 #![no_std]
 #![no_main]
 
-use core::cell::Cell;
-use core::future::poll_fn;
-use core::task::{Poll, Waker};
+use core::task::Waker;
 use defmt::*;
 use embassy_executor::Spawner;
-use embassy_sync::blocking_mutex::ThreadModeMutex;
 use embassy_time::Timer;
 use static_cell::StaticCell;
+use waker_churn::lost_wakeup::LostWakeupDetector;
+use waker_churn::{ChangeSignal, State};
 
 use {defmt_rtt as _, panic_probe as _};
 
-#[derive(Debug, Clone, Copy, Default, PartialEq, defmt::Format)]
-enum State {
-    #[default]
-    NotReady,
-    Ready(u32),
-}
-
-#[derive(Default)]
-struct Signal {
-    state: Cell<State>,
-    waker_registration: Cell<Option<Waker>>,
-}
-
-type SyncSignal = ThreadModeMutex<Signal>;
-
-async fn signal_wait(signal: &SyncSignal, current_state: State) {
-    poll_fn(|cx| {
-        trace!("Running waker with address: {:?}", cx.waker().data());
-        signal.lock(|s| {
-            if s.state.get() != current_state {
-                info!("Signal is ready");
-                Poll::Ready(())
-            } else {
-                info!("Signal not ready, registering waker");
-                s.waker_registration.set(Some(cx.waker().clone()));
-                Poll::Pending
-            }
-        })
-    })
-    .await;
-}
+/// The naive signal, but every waker which gets overwritten before it was woken is reported as a lost wakeup.
+type SyncSignal = waker_churn::SingleSlotSignal<LostWakeupDetector<Option<Waker>, 4>>;
 
 /// Problem here is that there is only one waker spot in the signal struct. An if the waker gets replaced, the original waker will not be called.
 /// Therefore, only one task will be woken up.
@@ -52,7 +22,7 @@ async fn main(spawner: Spawner) {
     info!("Hello World!");
 
     static SIGNAL: StaticCell<SyncSignal> = StaticCell::new();
-    let signal = SIGNAL.init(ThreadModeMutex::new(Signal::default()));
+    let signal = SIGNAL.init(SyncSignal::from_slot(LostWakeupDetector::new(None), ()));
     let mut counter = 0;
 
     spawner.must_spawn(wait_for_signal("TaskTwo", signal, true));
@@ -62,37 +32,11 @@ async fn main(spawner: Spawner) {
         Timer::after_millis(500).await;
         counter += 1;
 
-        signal.lock(|s| {
-            s.state.set(State::Ready(counter));
-
-            if let Some(waker) = s.waker_registration.take() {
-                info!("Wake!");
-                waker.wake_by_ref();
-                s.waker_registration.set(Some(waker));
-            } else {
-                info!("No waker");
-            }
-        });
+        signal.set(State::Ready(counter));
     }
 }
 
 #[embassy_executor::task(pool_size = 2)]
 async fn wait_for_signal(name: &'static str, signal: &'static SyncSignal, odd: bool) {
-    info!("Starting {} task", name);
-
-    loop {
-        let current_state = signal.lock(|s| s.state.get());
-
-        match (odd, current_state) {
-            (true, State::Ready(x)) if x % 2 == 1 => {
-                info!("{}: Odd state: {:?}", name, current_state);
-            }
-            (false, State::Ready(x)) if x % 2 == 0 => {
-                info!("{}: Even state: {:?}", name, current_state);
-            }
-            _ => {}
-        }
-
-        signal_wait(signal, current_state).await;
-    }
+    waker_churn::wait_for_signal(name, signal, odd).await;
 }
@@ -0,0 +1,59 @@
+//! Shared waker strategies of the waker churn demos.
+//!
+//! Every strategy stores the latest [`State`] and lets tasks wait until it changes.
+//! They only differ in how the waker of a waiting task is stored, which is exactly what the demos compare.
+//!
+//! [`stats`] counts the churn of a strategy per task and [`lost_wakeup`] reports tasks which starve in a single waker slot.
+//! [`ping_pong`] reports tasks which keep waking each other through a `WakerRegistration`.
+//! [`on_demand`] holds the peripheral wrapper of the on demand peripheral demo.
+//! [`names`] maps wakers to task names for the log output.
+//! With the `std` feature, [`sim`] replays the demos on the host with embassy's polling order
+//! and [`report`] compares all strategies across a matrix of scenarios.
+
+// make `std` available when testing or for the host-only tooling
+#![cfg_attr(not(any(test, feature = "std")), no_std)]
+
+// This mod MUST go first, so that the others see its macros.
+mod fmt;
+
+pub mod lost_wakeup;
+pub mod names;
+pub mod on_demand;
+pub mod ping_pong;
+#[cfg(feature = "std")]
+pub mod report;
+mod signal;
+#[cfg(feature = "std")]
+pub mod sim;
+mod state;
+pub mod stats;
+mod waker_id;
+
+#[cfg(test)]
+mod test_util;
+
+pub use signal::barrier::{Barrier, BarrierWaitResult};
+pub use signal::bounded::{
+    BoundedSignal, BoundedWaitError, BoundedWakerRegistration, Overflow, OverflowPolicy,
+};
+pub use signal::broadcast::{Broadcast, Received, Subscriber, subscribe_broadcast};
+pub use signal::coalescing::CoalescingSignal;
+pub use signal::condvar::{BlockingMutex, Condvar, wait_for_condvar};
+pub use signal::event_group::{EVEN_BIT, EventBits, EventGroup, ODD_BIT, wait_for_event_bit};
+pub use signal::filtered::{FilteredSignal, Predicate, wait_for_filtered_signal};
+pub use signal::multi_waker_registration::MultiWakerRegistrationSignal;
+pub use signal::priority::{
+    AtPriority, DEFAULT_PRIORITY, Priority, PrioritySignal, PriorityWaitQueue,
+};
+pub use signal::semaphore::{QueueFull, Semaphore, SemaphorePermit};
+pub use signal::single_slot::{
+    AtomicWakerSignal, NaiveSignal, SingleSlotSignal, WakerRegistrationSignal, WakerSlot,
+};
+pub use signal::versioned::{Changed, VersionedSignal, Watcher, watch_signal};
+pub use signal::wait_queue::WaitQueueSignal;
+pub use signal::{
+    ChangeSignal, Closed, HandOffSignal, SignalSet, WaitError, wait_any, wait_for_signal,
+    work_on_signal,
+};
+pub use state::State;
+pub use waker_id::WakerId;
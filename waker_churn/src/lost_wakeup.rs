@@ -0,0 +1,193 @@
+//! Detection of lost wakeups in single-slot waker storage.
+//!
+//! If a slot which holds the waker of one task is overwritten by the waker of another task,
+//! the first task is never woken again unless the slot wakes it on replacement. [`LostWakeupDetector`]
+//! wraps a [`WakerSlot`] and records every such replacement, so starvation shows up as an event instead
+//! of a task which silently stops logging.
+
+use core::task::Waker;
+
+use heapless::Deque;
+
+use crate::WakerId;
+use crate::names::task_name;
+use crate::signal::single_slot::WakerSlot;
+
+/// A stored waker which was replaced before it was ever woken.
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LostWakeup {
+    /// The task which lost its registration and will not be woken
+    pub lost: WakerId,
+    /// The task which registered its waker instead
+    pub replaced_by: WakerId,
+}
+
+/// Wraps a [`WakerSlot`] and keeps the last `N` [`LostWakeup`] events.
+///
+/// Slots which wake the replaced waker (like `WakerRegistration`) never lose a wakeup, so nothing is recorded for them.
+pub struct LostWakeupDetector<S, const N: usize> {
+    slot: S,
+    /// Copy of the stored waker, to compare it with the next one
+    stored: Option<Waker>,
+    /// True if `stored` was woken since it was registered
+    woken: bool,
+    events: Deque<LostWakeup, N>,
+    count: u32,
+}
+
+impl<S: WakerSlot, const N: usize> LostWakeupDetector<S, N> {
+    pub const fn new(slot: S) -> Self {
+        Self {
+            slot,
+            stored: None,
+            woken: false,
+            events: Deque::new(),
+            count: 0,
+        }
+    }
+
+    /// Total number of lost wakeups.
+    pub fn count(&self) -> u32 {
+        self.count
+    }
+
+    /// The last `N` lost wakeups, oldest first.
+    pub fn events(&self) -> impl Iterator<Item = &LostWakeup> {
+        self.events.iter()
+    }
+
+    fn record(&mut self, event: LostWakeup) {
+        warn!(
+            "Lost wakeup: waker of task {} replaced by task {} before it was woken",
+            task_name(event.lost),
+            task_name(event.replaced_by)
+        );
+
+        if self.events.is_full() {
+            self.events.pop_front();
+        }
+        let _ = self.events.push_back(event);
+        self.count += 1;
+    }
+}
+
+impl<S: WakerSlot, const N: usize> WakerSlot for LostWakeupDetector<S, N> {
+    const WAKES_ON_REPLACE: bool = S::WAKES_ON_REPLACE;
+    const KEEPS_WAKER_ON_WAKE: bool = S::KEEPS_WAKER_ON_WAKE;
+
+    fn register(&mut self, waker: &Waker) {
+        if let Some(old) = &self.stored
+            && !old.will_wake(waker)
+            && !self.woken
+            && !S::WAKES_ON_REPLACE
+        {
+            self.record(LostWakeup {
+                lost: WakerId::of(old),
+                replaced_by: WakerId::of(waker),
+            });
+        }
+
+        self.slot.register(waker);
+        self.stored = Some(waker.clone());
+        self.woken = false;
+    }
+
+    fn wake(&mut self) {
+        self.slot.wake();
+        self.woken = self.stored.is_some();
+    }
+
+    /// A removed waker is not lost, so the next registration is not reported.
+    fn clear(&mut self) {
+        self.slot.clear();
+        self.stored = None;
+        self.woken = false;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use embassy_sync::waitqueue::{AtomicWaker, WakerRegistration};
+
+    use super::*;
+    use crate::signal::single_slot::SingleSlotSignal;
+    use crate::sim::{RunOrder, Simulator};
+    use crate::test_util::{CountingWaker, in_thread_mode};
+    use crate::{ChangeSignal, State, wait_for_signal};
+
+    #[test]
+    fn test_replaced_before_wake() {
+        let (_, first) = CountingWaker::new();
+        let (_, second) = CountingWaker::new();
+        let mut detector = LostWakeupDetector::<Option<Waker>, 4>::new(None);
+
+        detector.register(&first);
+        // Same task again is fine
+        detector.register(&first.clone());
+        assert_eq!(detector.count(), 0);
+
+        detector.register(&second);
+        assert_eq!(detector.count(), 1);
+        assert_eq!(
+            detector.events().next(),
+            Some(&LostWakeup {
+                lost: WakerId::of(&first),
+                replaced_by: WakerId::of(&second),
+            })
+        );
+    }
+
+    #[test]
+    fn test_replaced_after_wake() {
+        let (_, first) = CountingWaker::new();
+        let (_, second) = CountingWaker::new();
+        let mut detector = LostWakeupDetector::<Option<Waker>, 4>::new(None);
+
+        detector.register(&first);
+        detector.wake();
+        detector.register(&second);
+        assert_eq!(detector.count(), 0);
+    }
+
+    #[test]
+    fn test_keeps_last_events() {
+        let wakers: Vec<_> = (0..4).map(|_| CountingWaker::new().1).collect();
+        let mut detector = LostWakeupDetector::<Option<Waker>, 2>::new(None);
+
+        for waker in &wakers {
+            detector.register(waker);
+        }
+
+        assert_eq!(detector.count(), 3);
+        let lost: Vec<_> = detector.events().map(|event| event.lost).collect();
+        assert_eq!(lost, [WakerId::of(&wakers[1]), WakerId::of(&wakers[2])]);
+    }
+
+    /// Run the two demo tasks and return the number of lost wakeups.
+    fn run<S: WakerSlot>(slot: S) -> u32 {
+        let signal = SingleSlotSignal::<_>::from_slot(LostWakeupDetector::<S, 4>::new(slot), ());
+        let mut sim = Simulator::new(RunOrder::Embassy);
+        sim.spawn("TaskTwo", wait_for_signal("TaskTwo", &signal, true));
+        sim.spawn("TaskOne", wait_for_signal("TaskOne", &signal, false));
+
+        for counter in 1..=3 {
+            sim.run_for(20);
+            signal.set(State::Ready(counter));
+        }
+        sim.run_for(20);
+
+        signal.slot(|detector| detector.count())
+    }
+
+    #[test]
+    fn test_demo_tasks() {
+        in_thread_mode(|| {
+            // TaskOne registers first and is starved by TaskTwo
+            assert_eq!(run(None), 1);
+            assert_eq!(run(AtomicWaker::new()), 1);
+            // Replacing wakes TaskOne, so nothing is lost (but see the ping-pong)
+            assert_eq!(run(WakerRegistration::new()), 0);
+        });
+    }
+}
@@ -0,0 +1,119 @@
+//! Registry which maps the waker of a task to the name it was spawned with.
+//!
+//! The tasks register themselves once (e.g. [`wait_for_signal`](crate::wait_for_signal) does it on start),
+//! afterwards the trace lines of the strategies print [`task_name`] instead of the data pointer of a waker.
+//! The name is removed again when the task exits, because the address of its waker may be reused by another task.
+
+use core::cell::RefCell;
+
+use embassy_sync::blocking_mutex::Mutex;
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use heapless::Vec;
+
+use crate::WakerId;
+
+/// Maximum number of named tasks
+const CAPACITY: usize = 16;
+
+/// Name of tasks which did not register a name.
+pub const UNKNOWN: &str = "<unknown>";
+
+static NAMES: Mutex<CriticalSectionRawMutex, RefCell<Vec<(WakerId, &'static str), CAPACITY>>> =
+    Mutex::new(RefCell::new(Vec::new()));
+
+/// Register the name of a task. A task which registers again is renamed.
+pub fn register_task_name(task: WakerId, name: &'static str) {
+    NAMES.lock(|names| {
+        let mut names = names.borrow_mut();
+
+        if let Some(entry) = names.iter_mut().find(|(t, _)| *t == task) {
+            entry.1 = name;
+        } else if names.push((task, name)).is_err() {
+            warn!("Task name registry is full, {} stays unnamed", name);
+        }
+    })
+}
+
+/// Remove the name of a task, e.g. when it exits.
+pub fn unregister_task_name(task: WakerId) {
+    NAMES.lock(|names| names.borrow_mut().retain(|(t, _)| *t != task))
+}
+
+/// Register the name of the task which is currently polling, until the returned [`TaskName`] is dropped.
+///
+/// Hold it for the lifetime of the task, so the name is removed when the task completes or is dropped.
+pub async fn register_current_task(name: &'static str) -> TaskName {
+    let task = WakerId::current().await;
+    register_task_name(task, name);
+    TaskName { task }
+}
+
+/// Name registration of a task, which is removed when this is dropped.
+#[must_use = "the name is removed right away if it is not held"]
+pub struct TaskName {
+    task: WakerId,
+}
+
+impl Drop for TaskName {
+    fn drop(&mut self) {
+        unregister_task_name(self.task);
+    }
+}
+
+/// Name of the task, or [`UNKNOWN`] if it did not register one.
+pub fn task_name(task: WakerId) -> &'static str {
+    NAMES.lock(|names| {
+        names
+            .borrow()
+            .iter()
+            .find(|(t, _)| *t == task)
+            .map_or(UNKNOWN, |(_, name)| *name)
+    })
+}
+
+/// Name of the task which is currently polling.
+pub async fn current_task_name() -> &'static str {
+    task_name(WakerId::current().await)
+}
+
+#[cfg(test)]
+mod tests {
+    use core::cell::Cell;
+
+    use super::*;
+    use crate::sim::{RunOrder, Simulator};
+    use crate::test_util::CountingWaker;
+
+    #[test]
+    fn test_register() {
+        let (_, first) = CountingWaker::new();
+        let (_, second) = CountingWaker::new();
+
+        register_task_name(WakerId::of(&first), "TaskOne");
+        assert_eq!(task_name(WakerId::of(&first)), "TaskOne");
+        // Clones of a waker belong to the same task
+        assert_eq!(task_name(WakerId::of(&first.clone())), "TaskOne");
+        assert_eq!(task_name(WakerId::of(&second)), UNKNOWN);
+
+        register_task_name(WakerId::of(&first), "Renamed");
+        assert_eq!(task_name(WakerId::of(&first)), "Renamed");
+
+        unregister_task_name(WakerId::of(&first));
+        assert_eq!(task_name(WakerId::of(&first)), UNKNOWN);
+    }
+
+    #[test]
+    fn test_removed_on_task_exit() {
+        let task = Cell::new(None);
+        let mut sim = Simulator::new(RunOrder::Embassy);
+        sim.spawn("Short", async {
+            let _name = register_current_task("Short").await;
+            task.set(Some(WakerId::current().await));
+            assert_eq!(current_task_name().await, "Short");
+        });
+        sim.run_for(1);
+
+        assert!(sim.tasks()[0].completed);
+        assert_eq!(task_name(task.get().unwrap()), UNKNOWN);
+    }
+}
@@ -0,0 +1,163 @@
+//! OnDemandOutput type which initializes the output only when needed.
+//! Uses maitake_sync::Mutex (wait queue) to synchronize access to the output.
+
+use core::{
+    cell::Cell,
+    ops::{Deref, DerefMut},
+};
+
+use crate::names::current_task_name;
+
+/// Maitake mutex uses a wait queue which calls wake in a FIFO order.
+/// So to be fair, the task which asked for the mutex first will be woken up first.
+type Mutex<T> = maitake_sync::Mutex<T>;
+
+/// Creates the output whenever the [`OnDemandOutput`] needs to (re)initialize it.
+///
+/// On target this is the pin configuration of a GPIO output, on host it can be a mock.
+pub trait OutputFactory {
+    /// The initialized output peripheral.
+    type Output;
+
+    /// Initialize the output peripheral.
+    fn create(&self) -> Self::Output;
+}
+
+/// Output peripheral wrapper which is used to initialize the output only when needed.
+type MutexInner<F> = Option<<F as OutputFactory>::Output>;
+
+/// MutexGuard wrapper to have a custom drop implementation.
+pub struct OutputGuard<'a, F: OutputFactory> {
+    /// The output peripheral.
+    inner: maitake_sync::MutexGuard<'a, MutexInner<F>>,
+    /// The reference count to the output -> We need to decrement it when the guard is dropped.
+    reference_count: ReferenceCount<'a>,
+}
+
+impl<F: OutputFactory> Deref for OutputGuard<'_, F> {
+    type Target = F::Output;
+
+    fn deref(&self) -> &Self::Target {
+        self.inner.as_ref().unwrap()
+    }
+}
+
+impl<F: OutputFactory> DerefMut for OutputGuard<'_, F> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        self.inner.as_mut().unwrap()
+    }
+}
+
+impl<F: OutputFactory> Drop for OutputGuard<'_, F> {
+    fn drop(&mut self) {
+        trace!("{}: Dropping MutexGuard", self.reference_count.task);
+
+        let current_refcount = self.reference_count.get();
+
+        if current_refcount == 1 {
+            debug!(
+                "{}: Last reference dropped, deinitializing output by dropping it",
+                self.reference_count.task
+            );
+            // Deinitialize the output via dropping the inner peripheral
+            self.inner.take();
+        }
+    }
+}
+
+/// Reference count wrapper which increments the reference count when created and decrements it when dropped.
+/// This is used to make `get_or_init` cancel safe.
+struct ReferenceCount<'a> {
+    /// The reference count to the output
+    count: &'a Cell<usize>,
+    /// Name of the task which holds the reference, for the log output
+    task: &'static str,
+}
+
+impl Deref for ReferenceCount<'_> {
+    type Target = Cell<usize>;
+
+    fn deref(&self) -> &Self::Target {
+        self.count
+    }
+}
+
+impl<'a> ReferenceCount<'a> {
+    /// Create a new reference count wrapper which increments the reference count.
+    fn new(count: &'a Cell<usize>, task: &'static str) -> Self {
+        count.set(count.get() + 1);
+        trace!("{}: Incremented reference count: {}", task, count.get());
+        Self { count, task }
+    }
+}
+
+/// The reference count is decremented when the wrapper is dropped.
+/// So if there is a cancelation, the reference count will be decremented, because it is never stored in a MutexGuard.
+impl Drop for ReferenceCount<'_> {
+    fn drop(&mut self) {
+        let current_refcount = self.count.get();
+        debug_assert!(current_refcount > 0, "Reference count is already 0");
+
+        trace!(
+            "{}: Dropping ReferenceCount. Decrementing reference count from: {}",
+            self.task, current_refcount
+        );
+        self.count.set(current_refcount - 1);
+    }
+}
+
+/// Wannabe OnDemandOutput type which initializes the Output only when needed.
+/// And deinitializes it when the last reference is dropped.
+/// Useful for low power applications where we want to avoid a lock on on entering stop mode because of an active peripheral.
+pub struct OnDemandOutput<F: OutputFactory> {
+    // -- Configuration --
+    /// Creates the output peripheral
+    factory: F,
+
+    // -- Peripheral --
+    /// The actual peripheral
+    output: Mutex<MutexInner<F>>,
+    /// No need for atomics whatsoever, because multithreaded not possible without send + sync?
+    reference_count: Cell<usize>,
+}
+
+impl<F: OutputFactory> OnDemandOutput<F> {
+    pub fn new(factory: F) -> Self {
+        Self {
+            factory,
+            output: Mutex::new(None),
+            reference_count: Cell::new(0),
+        }
+    }
+
+    /// Get the output peripheral, initializing if needed.
+    pub async fn get_or_init(&self) -> OutputGuard<'_, F> {
+        // Create a reference count wrapper which increments the reference count
+        let task = current_task_name().await;
+        let reference_count = ReferenceCount::new(&self.reference_count, task);
+        trace!(
+            "{}: Waiting for lock. Reference count: {}",
+            task,
+            reference_count.get()
+        );
+
+        // Lock the output mutex after the reference count mutex
+        // If the future is canceled, `reference_count` will be dropped and the reference count will be decremented
+        let mut output = self.output.lock().await;
+        trace!("{}: Got output mutex", task);
+
+        // Initialize the output if it is not initialized yet
+        if output.is_none() {
+            debug!("{}: Initializing output..", task);
+
+            *output = Some(self.factory.create());
+        } else {
+            debug!("{}: Output already initialized", task);
+        };
+
+        OutputGuard {
+            inner: output,
+            reference_count,
+        }
+    }
+}
@@ -0,0 +1,196 @@
+//! Detection of wakers which take turns in a `WakerRegistration`.
+//!
+//! `WakerRegistration::register` wakes the stored waker if it is replaced by a different one. With two waiting tasks
+//! every poll of one task wakes the other, so the executor never gets idle and the MCU never reaches low-power mode.
+//! [`PingPongDetector`] wraps the slot and watches a sliding window of the last registrations. If every registration
+//! in the window replaced the waker of another task, the tasks play ping-pong: a warning is logged and a counter is incremented.
+
+use core::task::Waker;
+
+use heapless::Deque;
+
+use crate::WakerId;
+use crate::names::{UNKNOWN, task_name};
+use crate::signal::single_slot::WakerSlot;
+
+/// Wraps a [`WakerSlot`] and reports wakers which take turns over the last `N` registrations.
+///
+/// Every ping-pong is counted once, until a task registers again without replacing another one.
+pub struct PingPongDetector<S, const N: usize> {
+    slot: S,
+    /// Tasks of the last registrations, each one replaced the one before
+    window: Deque<WakerId, N>,
+    /// True while the window is a ping-pong which was already reported
+    active: bool,
+    count: u32,
+}
+
+impl<S: WakerSlot, const N: usize> PingPongDetector<S, N> {
+    pub const fn new(slot: S) -> Self {
+        const {
+            assert!(
+                N >= 2,
+                "taking turns needs a window of at least two registrations"
+            )
+        };
+        Self {
+            slot,
+            window: Deque::new(),
+            active: false,
+            count: 0,
+        }
+    }
+
+    /// Number of detected ping-pongs. Anything but 0 means the slot has more than one waiter,
+    /// so a multi-waiter strategy (`MultiWakerRegistration`, `WaitQueue`) should be used instead.
+    pub fn count(&self) -> u32 {
+        self.count
+    }
+
+    /// True if the last `N` registrations took turns.
+    pub fn is_ping_pong(&self) -> bool {
+        self.active
+    }
+
+    fn detected(&mut self) {
+        self.active = true;
+        self.count += 1;
+
+        let first = self.window.front().copied();
+        let other = self.window.iter().find(|task| Some(**task) != first);
+        warn!(
+            "Ping-pong: tasks {} and {} took turns in the last {} registrations of a single waker slot. Use a multi-waiter strategy like MultiWakerRegistration or WaitQueue instead.",
+            first.map_or(UNKNOWN, task_name),
+            other.map_or(UNKNOWN, |task| task_name(*task)),
+            N
+        );
+    }
+}
+
+impl<S: WakerSlot, const N: usize> WakerSlot for PingPongDetector<S, N> {
+    const WAKES_ON_REPLACE: bool = S::WAKES_ON_REPLACE;
+    const KEEPS_WAKER_ON_WAKE: bool = S::KEEPS_WAKER_ON_WAKE;
+
+    fn register(&mut self, waker: &Waker) {
+        let task = WakerId::of(waker);
+
+        if self.window.back() == Some(&task) {
+            // The same task registered again, so nobody took its turn
+            self.window.clear();
+            self.active = false;
+        } else if self.window.is_full() {
+            self.window.pop_front();
+        }
+        let _ = self.window.push_back(task);
+
+        if self.window.is_full() && !self.active {
+            self.detected();
+        }
+
+        self.slot.register(waker);
+    }
+
+    /// A slot which forgets its waker on wake is empty afterwards, so the next registration does not replace anyone.
+    fn wake(&mut self) {
+        self.slot.wake();
+        if !S::KEEPS_WAKER_ON_WAKE {
+            self.window.clear();
+            self.active = false;
+        }
+    }
+
+    /// The next registration does not replace anyone, so the turns start over.
+    fn clear(&mut self) {
+        self.slot.clear();
+        self.window.clear();
+        self.active = false;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use embassy_sync::waitqueue::WakerRegistration;
+
+    use super::*;
+    use crate::signal::single_slot::SingleSlotSignal;
+    use crate::sim::{RunOrder, Simulator};
+    use crate::test_util::{CountingWaker, in_thread_mode};
+    use crate::{ChangeSignal, State, wait_for_signal};
+
+    #[test]
+    fn test_taking_turns() {
+        let (_, first) = CountingWaker::new();
+        let (_, second) = CountingWaker::new();
+        let mut detector = PingPongDetector::<WakerRegistration, 4>::new(WakerRegistration::new());
+
+        for waker in [&first, &second, &first] {
+            detector.register(waker);
+        }
+        assert_eq!(detector.count(), 0);
+
+        detector.register(&second);
+        assert!(detector.is_ping_pong());
+        assert_eq!(detector.count(), 1);
+
+        // Still the same ping-pong
+        detector.register(&first);
+        assert_eq!(detector.count(), 1);
+    }
+
+    #[test]
+    fn test_same_task_ends_ping_pong() {
+        let (_, first) = CountingWaker::new();
+        let (_, second) = CountingWaker::new();
+        let mut detector = PingPongDetector::<WakerRegistration, 2>::new(WakerRegistration::new());
+
+        detector.register(&first);
+        detector.register(&second);
+        assert_eq!(detector.count(), 1);
+
+        detector.register(&second);
+        assert!(!detector.is_ping_pong());
+
+        detector.register(&first);
+        assert_eq!(detector.count(), 2);
+    }
+
+    #[test]
+    fn test_updates_between_registrations() {
+        let (_, first) = CountingWaker::new();
+        let (_, second) = CountingWaker::new();
+        let mut detector = PingPongDetector::<WakerRegistration, 2>::new(WakerRegistration::new());
+
+        // Every task is woken by an update before the other one registers, nobody gets replaced
+        for waker in [&first, &second, &first, &second] {
+            detector.register(waker);
+            detector.wake();
+        }
+        assert!(!detector.is_ping_pong());
+        assert_eq!(detector.count(), 0);
+    }
+
+    /// Run the two demo tasks and return the number of ping-pongs.
+    fn run<S: WakerSlot>(slot: S) -> u32 {
+        let signal = SingleSlotSignal::<_>::from_slot(PingPongDetector::<S, 8>::new(slot), ());
+        let mut sim = Simulator::new(RunOrder::Embassy);
+        sim.spawn("TaskTwo", wait_for_signal("TaskTwo", &signal, true));
+        sim.spawn("TaskOne", wait_for_signal("TaskOne", &signal, false));
+
+        for counter in 1..=3 {
+            sim.run_for(20);
+            signal.set(State::Ready(counter));
+        }
+        sim.run_for(20);
+
+        signal.slot(|detector| detector.count())
+    }
+
+    #[test]
+    fn test_demo_tasks() {
+        in_thread_mode(|| {
+            assert!(run(WakerRegistration::new()) > 0);
+            // Without waking on replace the tasks don't take turns (one of them starves instead)
+            assert_eq!(run(None), 0);
+        });
+    }
+}
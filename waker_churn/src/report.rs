@@ -0,0 +1,658 @@
+//! Comparison of all strategies across a matrix of scenarios, exported as JSON or CSV.
+//!
+//! Every [`Scenario`] spawns `waiters` tasks on the [`Simulator`] which filter the odd or even states like
+//! [`wait_for_signal`](crate::wait_for_signal), and publishes [`UPDATES`] states every `period` ticks.
+//! The resulting [`Row`] holds the numbers which the README describes in prose:
+//! how many polls an update costs, how many updates a waiter never saw, how often a waiter was woken for nothing
+//! and how long the slowest waiter took to see an update.
+//!
+//! [`broadcast_matrix`] compares the lossless [`Broadcast`] with `embassy_sync`'s `PubSubChannel` in the same way:
+//! how many states the subscribers received and lost, how many polls a received state costs and how much RAM it takes.
+
+use std::cell::{Cell, RefCell};
+use std::io::{self, Write};
+use std::vec::Vec;
+
+use embassy_sync::blocking_mutex::raw::ThreadModeRawMutex;
+use embassy_sync::pubsub::{self, PubSubChannel, WaitResult};
+
+use crate::sim::{Clock, RunOrder, Simulator};
+use crate::{
+    AtomicWakerSignal, Broadcast, ChangeSignal, MultiWakerRegistrationSignal, NaiveSignal,
+    Received, State, Subscriber, WaitQueueSignal, WakerRegistrationSignal,
+};
+
+/// Number of states published per scenario.
+pub const UPDATES: u32 = 8;
+
+/// Waiter counts of the [`matrix`].
+pub const WAITERS: core::ops::RangeInclusive<usize> = 1..=16;
+
+/// Update periods of the [`matrix`], in ticks. One tick is one poll.
+pub const PERIODS: [u64; 4] = [1, 8, 64, 500];
+
+/// Capacity of the `MultiWakerRegistration`, large enough for every waiter count of the [`matrix`].
+const MULTI_WAKER_CAPACITY: usize = 16;
+
+/// The waker strategies of the demos.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Strategy {
+    Naive,
+    AtomicWaker,
+    WakerRegistration,
+    MultiWakerRegistration,
+    WaitQueue,
+}
+
+impl Strategy {
+    pub const ALL: [Strategy; 5] = [
+        Strategy::Naive,
+        Strategy::AtomicWaker,
+        Strategy::WakerRegistration,
+        Strategy::MultiWakerRegistration,
+        Strategy::WaitQueue,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Strategy::Naive => "naive",
+            Strategy::AtomicWaker => "atomic_waker",
+            Strategy::WakerRegistration => "waker_registration",
+            Strategy::MultiWakerRegistration => "multi_waker_registration",
+            Strategy::WaitQueue => "wait_queue",
+        }
+    }
+}
+
+/// Which states the waiters are interested in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Mix {
+    /// All waiters wait for odd states.
+    Odd,
+    /// All waiters wait for even states.
+    Even,
+    /// Every other waiter waits for odd states, like `TaskTwo` and `TaskOne` of the demos.
+    Alternating,
+}
+
+impl Mix {
+    pub const ALL: [Mix; 3] = [Mix::Odd, Mix::Even, Mix::Alternating];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Mix::Odd => "odd",
+            Mix::Even => "even",
+            Mix::Alternating => "alternating",
+        }
+    }
+
+    /// True if the waiter with the given index waits for odd states.
+    fn odd(self, waiter: usize) -> bool {
+        match self {
+            Mix::Odd => true,
+            Mix::Even => false,
+            // The first waiter is spawned first, like `TaskTwo` of the demos
+            Mix::Alternating => waiter.is_multiple_of(2),
+        }
+    }
+}
+
+/// One entry of the comparison.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Scenario {
+    pub strategy: Strategy,
+    pub waiters: usize,
+    /// Ticks between two updates
+    pub period: u64,
+    pub mix: Mix,
+}
+
+/// Result of a [`Scenario`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct Row {
+    pub scenario: Scenario,
+    /// Polls of all waiters per update, without the first poll of every waiter
+    pub polls_per_update: f64,
+    /// Updates which a waiter was interested in, but never saw, summed over all waiters
+    pub missed_updates: u64,
+    /// Wakes which did not let the waiter see an update it was interested in, summed over all waiters
+    pub wasted_wakes: u64,
+    /// Most ticks between publishing an update and a waiter seeing it. `None` if no waiter saw any update.
+    pub worst_latency: Option<u64>,
+}
+
+/// All scenarios: every strategy with every waiter count, period and mix.
+pub fn matrix() -> impl Iterator<Item = Scenario> {
+    Strategy::ALL.into_iter().flat_map(|strategy| {
+        WAITERS.flat_map(move |waiters| {
+            PERIODS.into_iter().flat_map(move |period| {
+                Mix::ALL.into_iter().map(move |mix| Scenario {
+                    strategy,
+                    waiters,
+                    period,
+                    mix,
+                })
+            })
+        })
+    })
+}
+
+/// Run the scenario on the simulator.
+///
+/// Like `ThreadModeMutex` the strategies must be used from a thread named `main` on std.
+pub fn run(scenario: Scenario) -> Row {
+    match scenario.strategy {
+        Strategy::Naive => run_with::<NaiveSignal>(scenario),
+        Strategy::AtomicWaker => run_with::<AtomicWakerSignal>(scenario),
+        Strategy::WakerRegistration => run_with::<WakerRegistrationSignal>(scenario),
+        Strategy::MultiWakerRegistration => {
+            run_with::<MultiWakerRegistrationSignal<MULTI_WAKER_CAPACITY>>(scenario)
+        }
+        Strategy::WaitQueue => run_with::<WaitQueueSignal>(scenario),
+    }
+}
+
+/// Same loop as `wait_for_signal`, but records the interesting states together with the time they were seen.
+async fn waiter<S: ChangeSignal>(
+    signal: &S,
+    odd: bool,
+    clock: Clock,
+    seen: &RefCell<Vec<(u32, u64)>>,
+) {
+    loop {
+        let current_state = signal.get();
+
+        if let State::Ready(x) = current_state
+            && (x % 2 == 1) == odd
+        {
+            seen.borrow_mut().push((x, clock.now()));
+        }
+
+        if signal.wait_changed(&current_state).await.is_err() {
+            return;
+        }
+    }
+}
+
+fn run_with<S: ChangeSignal + Default>(scenario: Scenario) -> Row {
+    let signal = &S::default();
+    let seen: Vec<_> = (0..scenario.waiters)
+        .map(|_| RefCell::new(Vec::new()))
+        .collect();
+    let mut sim = Simulator::new(RunOrder::Embassy);
+
+    for (waiter_index, seen) in seen.iter().enumerate() {
+        let odd = scenario.mix.odd(waiter_index);
+        sim.spawn("Waiter", waiter(signal, odd, sim.clock(), seen));
+    }
+
+    // Time at which each update was published, indexed by `value - 1`
+    let mut published = Vec::new();
+    for value in 1..=UPDATES {
+        sim.run_for(scenario.period);
+        published.push(sim.now());
+        signal.set(State::Ready(value));
+    }
+    sim.run_for(scenario.period);
+
+    let mut missed_updates = 0;
+    let mut wasted_wakes = 0;
+    let mut worst_latency = None;
+
+    for (waiter_index, seen) in seen.iter().enumerate() {
+        let odd = scenario.mix.odd(waiter_index);
+        let seen = seen.borrow();
+
+        let interested = (1..=UPDATES).filter(|x| (x % 2 == 1) == odd).count() as u64;
+        missed_updates += interested - seen.len() as u64;
+        wasted_wakes += sim
+            .task(waiter_index)
+            .wakes
+            .saturating_sub(seen.len() as u64);
+
+        for &(value, at) in seen.iter() {
+            let latency = at - published[value as usize - 1];
+            worst_latency = worst_latency.max(Some(latency));
+        }
+    }
+
+    let first_polls = scenario.waiters as u64;
+    Row {
+        scenario,
+        polls_per_update: (sim.total_polls() - first_polls) as f64 / UPDATES as f64,
+        missed_updates,
+        wasted_wakes,
+        worst_latency,
+    }
+}
+
+const COLUMNS: [&str; 8] = [
+    "strategy",
+    "waiters",
+    "period",
+    "mix",
+    "polls_per_update",
+    "missed_updates",
+    "wasted_wakes",
+    "worst_latency",
+];
+
+/// Write the rows as CSV with a header line. A missing latency is an empty field.
+pub fn write_csv(rows: &[Row], mut out: impl Write) -> io::Result<()> {
+    writeln!(out, "{}", COLUMNS.join(","))?;
+
+    for row in rows {
+        let s = &row.scenario;
+        writeln!(
+            out,
+            "{},{},{},{},{:.2},{},{},{}",
+            s.strategy.name(),
+            s.waiters,
+            s.period,
+            s.mix.name(),
+            row.polls_per_update,
+            row.missed_updates,
+            row.wasted_wakes,
+            row.worst_latency.map(|l| l.to_string()).unwrap_or_default(),
+        )?;
+    }
+
+    Ok(())
+}
+
+/// Write the rows as a JSON array of objects with the same fields as the CSV. A missing latency is `null`.
+pub fn write_json(rows: &[Row], mut out: impl Write) -> io::Result<()> {
+    writeln!(out, "[")?;
+
+    for (i, row) in rows.iter().enumerate() {
+        let s = &row.scenario;
+        let separator = if i + 1 < rows.len() { "," } else { "" };
+        writeln!(
+            out,
+            "  {{\"strategy\": \"{}\", \"waiters\": {}, \"period\": {}, \"mix\": \"{}\", \"polls_per_update\": {:.2}, \"missed_updates\": {}, \"wasted_wakes\": {}, \"worst_latency\": {}}}{}",
+            s.strategy.name(),
+            s.waiters,
+            s.period,
+            s.mix.name(),
+            row.polls_per_update,
+            row.missed_updates,
+            row.wasted_wakes,
+            row.worst_latency
+                .map(|l| l.to_string())
+                .unwrap_or_else(|| "null".into()),
+            separator,
+        )?;
+    }
+
+    writeln!(out, "]")
+}
+
+/// Number of rounds per [`BroadcastScenario`].
+pub const ROUNDS: u32 = 8;
+
+/// Capacity of both broadcast channels.
+pub const BROADCAST_CAPACITY: usize = 8;
+
+/// Subscriber counts of the [`broadcast_matrix`].
+pub const SUBSCRIBERS: [usize; 3] = [1, 4, 16];
+
+/// `PubSubChannel` needs the maximum number of subscribers upfront.
+const MAX_SUBSCRIBERS: usize = 16;
+
+/// States published back to back per round, below and above the capacity.
+pub const BURSTS: [u32; 3] = [1, 4, 16];
+
+type PubSub = PubSubChannel<ThreadModeRawMutex, State, BROADCAST_CAPACITY, MAX_SUBSCRIBERS, 1>;
+type PubSubSubscriber<'a> =
+    pubsub::Subscriber<'a, ThreadModeRawMutex, State, BROADCAST_CAPACITY, MAX_SUBSCRIBERS, 1>;
+
+/// The broadcast channels to compare.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Channel {
+    Broadcast,
+    PubSub,
+}
+
+impl Channel {
+    pub const ALL: [Channel; 2] = [Channel::Broadcast, Channel::PubSub];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Channel::Broadcast => "broadcast",
+            Channel::PubSub => "pub_sub_channel",
+        }
+    }
+
+    /// Bytes of the channel and of all its subscriber handles.
+    pub fn ram_bytes(self, subscribers: usize) -> usize {
+        match self {
+            Channel::Broadcast => {
+                size_of::<Broadcast<BROADCAST_CAPACITY>>()
+                    + subscribers * size_of::<Subscriber<'static, BROADCAST_CAPACITY>>()
+            }
+            Channel::PubSub => {
+                size_of::<PubSub>() + subscribers * size_of::<PubSubSubscriber<'static>>()
+            }
+        }
+    }
+}
+
+/// One entry of the broadcast comparison.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BroadcastScenario {
+    pub channel: Channel,
+    pub subscribers: usize,
+    /// States published back to back every `period` ticks
+    pub burst: u32,
+    /// Ticks between two bursts
+    pub period: u64,
+}
+
+/// Result of a [`BroadcastScenario`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct BroadcastRow {
+    pub scenario: BroadcastScenario,
+    /// States received, summed over all subscribers
+    pub received: u64,
+    /// States reported as lost, summed over all subscribers
+    pub lost: u64,
+    /// Polls of all subscribers per received state, without the first poll of every subscriber
+    pub polls_per_value: f64,
+    /// Size of the channel and its subscriber handles
+    pub ram_bytes: usize,
+}
+
+/// All broadcast scenarios: both channels with every subscriber count, burst and period.
+pub fn broadcast_matrix() -> impl Iterator<Item = BroadcastScenario> {
+    Channel::ALL.into_iter().flat_map(|channel| {
+        SUBSCRIBERS.into_iter().flat_map(move |subscribers| {
+            BURSTS.into_iter().flat_map(move |burst| {
+                PERIODS.into_iter().map(move |period| BroadcastScenario {
+                    channel,
+                    subscribers,
+                    burst,
+                    period,
+                })
+            })
+        })
+    })
+}
+
+/// Run the broadcast scenario on the simulator. Must be called from a thread named `main`, like [`run`].
+pub fn run_broadcast(scenario: BroadcastScenario) -> BroadcastRow {
+    let counts: Vec<Cell<(u64, u64)>> = (0..scenario.subscribers)
+        .map(|_| Cell::new((0, 0)))
+        .collect();
+
+    let polls = match scenario.channel {
+        Channel::Broadcast => {
+            let broadcast = &Broadcast::<BROADCAST_CAPACITY>::new();
+            let mut sim = Simulator::new(RunOrder::Embassy);
+            for counts in counts.iter() {
+                // Subscribe before the first state is published, not on the first poll
+                sim.spawn(
+                    "Subscriber",
+                    receive_broadcast(broadcast.subscriber(), counts),
+                );
+            }
+            publish_rounds(&mut sim, scenario, |state| broadcast.publish(state))
+        }
+        Channel::PubSub => {
+            let channel = &PubSub::new();
+            let publisher = channel.immediate_publisher();
+            let mut sim = Simulator::new(RunOrder::Embassy);
+            for counts in counts.iter() {
+                let subscriber = channel.subscriber().expect("Too many subscribers");
+                sim.spawn("Subscriber", receive_pub_sub(subscriber, counts));
+            }
+            publish_rounds(&mut sim, scenario, |state| {
+                publisher.publish_immediate(state)
+            })
+        }
+    };
+
+    let (received, lost) = counts
+        .iter()
+        .map(Cell::get)
+        .fold((0, 0), |(r, l), (received, lost)| (r + received, l + lost));
+
+    let first_polls = scenario.subscribers as u64;
+    BroadcastRow {
+        scenario,
+        received,
+        lost,
+        polls_per_value: (polls - first_polls) as f64 / received.max(1) as f64,
+        ram_bytes: scenario.channel.ram_bytes(scenario.subscribers),
+    }
+}
+
+/// Publish [`ROUNDS`] bursts and return the total number of polls.
+fn publish_rounds(
+    sim: &mut Simulator,
+    scenario: BroadcastScenario,
+    publish: impl Fn(State),
+) -> u64 {
+    let mut value = 0;
+    for _ in 0..ROUNDS {
+        sim.run_for(scenario.period);
+        for _ in 0..scenario.burst {
+            value += 1;
+            publish(State::Ready(value));
+        }
+    }
+    sim.run_for(scenario.period);
+
+    sim.total_polls()
+}
+
+/// Count the received and lost states of a [`Broadcast`] subscriber.
+async fn receive_broadcast(
+    mut subscriber: Subscriber<'_, BROADCAST_CAPACITY>,
+    counts: &Cell<(u64, u64)>,
+) {
+    while let Ok(received) = subscriber.next().await {
+        let (received_count, lost) = counts.get();
+        counts.set(match received {
+            Received::Value(_) => (received_count + 1, lost),
+            Received::Lagged(n) => (received_count, lost + n as u64),
+        });
+    }
+}
+
+/// Count the received and lost states of a `PubSubChannel` subscriber.
+async fn receive_pub_sub(mut subscriber: PubSubSubscriber<'_>, counts: &Cell<(u64, u64)>) {
+    loop {
+        let (received, lost) = counts.get();
+        counts.set(match subscriber.next_message().await {
+            WaitResult::Message(_) => (received + 1, lost),
+            WaitResult::Lagged(n) => (received, lost + n),
+        });
+    }
+}
+
+const BROADCAST_COLUMNS: [&str; 8] = [
+    "channel",
+    "subscribers",
+    "burst",
+    "period",
+    "received",
+    "lost",
+    "polls_per_value",
+    "ram_bytes",
+];
+
+/// Write the broadcast rows as CSV with a header line.
+pub fn write_broadcast_csv(rows: &[BroadcastRow], mut out: impl Write) -> io::Result<()> {
+    writeln!(out, "{}", BROADCAST_COLUMNS.join(","))?;
+
+    for row in rows {
+        let s = &row.scenario;
+        writeln!(
+            out,
+            "{},{},{},{},{},{},{:.2},{}",
+            s.channel.name(),
+            s.subscribers,
+            s.burst,
+            s.period,
+            row.received,
+            row.lost,
+            row.polls_per_value,
+            row.ram_bytes,
+        )?;
+    }
+
+    Ok(())
+}
+
+/// Write the broadcast rows as a JSON array of objects with the same fields as the CSV.
+pub fn write_broadcast_json(rows: &[BroadcastRow], mut out: impl Write) -> io::Result<()> {
+    writeln!(out, "[")?;
+
+    for (i, row) in rows.iter().enumerate() {
+        let s = &row.scenario;
+        let separator = if i + 1 < rows.len() { "," } else { "" };
+        writeln!(
+            out,
+            "  {{\"channel\": \"{}\", \"subscribers\": {}, \"burst\": {}, \"period\": {}, \"received\": {}, \"lost\": {}, \"polls_per_value\": {:.2}, \"ram_bytes\": {}}}{}",
+            s.channel.name(),
+            s.subscribers,
+            s.burst,
+            s.period,
+            row.received,
+            row.lost,
+            row.polls_per_value,
+            row.ram_bytes,
+            separator,
+        )?;
+    }
+
+    writeln!(out, "]")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::in_thread_mode;
+
+    fn scenario(strategy: Strategy, waiters: usize, mix: Mix) -> Scenario {
+        Scenario {
+            strategy,
+            waiters,
+            period: 500,
+            mix,
+        }
+    }
+
+    #[test]
+    fn test_wake_all_strategies() {
+        in_thread_mode(|| {
+            for strategy in [Strategy::MultiWakerRegistration, Strategy::WaitQueue] {
+                let row = run(scenario(strategy, 3, Mix::Alternating));
+
+                // Every waiter is polled once per update, and sees it in the batch right after the update
+                assert_eq!(row.polls_per_update, 3.0, "{strategy:?}");
+                assert_eq!(row.missed_updates, 0, "{strategy:?}");
+                assert_eq!(row.worst_latency, Some(3), "{strategy:?}");
+                // ...but is also woken for the updates of the other parity
+                assert_eq!(row.wasted_wakes, 3 * UPDATES as u64 / 2, "{strategy:?}");
+            }
+        });
+    }
+
+    #[test]
+    fn test_single_slot_misses_updates() {
+        in_thread_mode(|| {
+            for strategy in [Strategy::Naive, Strategy::AtomicWaker] {
+                let row = run(scenario(strategy, 2, Mix::Alternating));
+
+                // The waiter which registered first never sees its updates
+                assert_eq!(row.missed_updates, UPDATES as u64 / 2, "{strategy:?}");
+            }
+
+            let row = run(scenario(Strategy::WakerRegistration, 2, Mix::Alternating));
+            assert_eq!(row.missed_updates, 0);
+            // One poll per tick because of the ping-pong, instead of one per waiter and update
+            assert!(row.polls_per_update > 500.0);
+        });
+    }
+
+    #[test]
+    fn test_export() {
+        let rows = [Row {
+            scenario: scenario(Strategy::Naive, 2, Mix::Odd),
+            polls_per_update: 1.0,
+            missed_updates: 4,
+            wasted_wakes: 0,
+            worst_latency: None,
+        }];
+
+        let mut csv = Vec::new();
+        write_csv(&rows, &mut csv).unwrap();
+        assert_eq!(
+            String::from_utf8(csv).unwrap(),
+            "strategy,waiters,period,mix,polls_per_update,missed_updates,wasted_wakes,worst_latency\n\
+             naive,2,500,odd,1.00,4,0,\n"
+        );
+
+        let mut json = Vec::new();
+        write_json(&rows, &mut json).unwrap();
+        assert_eq!(
+            String::from_utf8(json).unwrap(),
+            "[\n  {\"strategy\": \"naive\", \"waiters\": 2, \"period\": 500, \"mix\": \"odd\", \"polls_per_update\": 1.00, \"missed_updates\": 4, \"wasted_wakes\": 0, \"worst_latency\": null}\n]\n"
+        );
+    }
+
+    fn broadcast_scenario(channel: Channel, burst: u32) -> BroadcastScenario {
+        BroadcastScenario {
+            channel,
+            subscribers: 4,
+            burst,
+            period: 500,
+        }
+    }
+
+    #[test]
+    fn test_broadcast_is_lossless_within_capacity() {
+        in_thread_mode(|| {
+            for channel in Channel::ALL {
+                let row = run_broadcast(broadcast_scenario(channel, 4));
+                assert_eq!(row.received, 4 * 4 * ROUNDS as u64, "{channel:?}");
+                assert_eq!(row.lost, 0, "{channel:?}");
+            }
+        });
+    }
+
+    #[test]
+    fn test_broadcast_reports_lag_like_pub_sub() {
+        in_thread_mode(|| {
+            let broadcast = run_broadcast(broadcast_scenario(Channel::Broadcast, 16));
+            let pub_sub = run_broadcast(broadcast_scenario(Channel::PubSub, 16));
+
+            // Every subscriber keeps the last `BROADCAST_CAPACITY` states of a burst and loses the rest
+            let lost = 4 * (16 - BROADCAST_CAPACITY as u64) * ROUNDS as u64;
+            assert_eq!(broadcast.lost, lost);
+            assert_eq!(pub_sub.lost, lost);
+            assert_eq!(broadcast.received, pub_sub.received);
+            assert_eq!(broadcast.received + broadcast.lost, 4 * 16 * ROUNDS as u64);
+        });
+    }
+
+    #[test]
+    fn test_broadcast_ram() {
+        // The broadcast only stores the states and a read position per subscriber,
+        // the channel also keeps a counter per state and a waker per subscriber
+        assert!(Channel::Broadcast.ram_bytes(16) < Channel::PubSub.ram_bytes(16));
+    }
+
+    #[test]
+    fn test_matrix() {
+        assert_eq!(
+            matrix().count(),
+            Strategy::ALL.len() * WAITERS.count() * PERIODS.len() * Mix::ALL.len()
+        );
+        assert_eq!(
+            broadcast_matrix().count(),
+            Channel::ALL.len() * SUBSCRIBERS.len() * BURSTS.len() * PERIODS.len()
+        );
+    }
+}
@@ -0,0 +1,203 @@
+use core::cell::Cell;
+
+use embassy_sync::blocking_mutex::Mutex;
+use embassy_sync::blocking_mutex::raw::{RawMutex, ThreadModeRawMutex};
+use maitake_sync::WaitQueue;
+
+use crate::signal::OnDrop;
+
+/// Result of [`Barrier::wait`].
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BarrierWaitResult {
+    leader: bool,
+}
+
+impl BarrierWaitResult {
+    /// True for exactly one task of every round: the one which arrived last and released the others.
+    pub fn is_leader(&self) -> bool {
+        self.leader
+    }
+}
+
+#[derive(Clone, Copy)]
+struct Round {
+    /// Number of tasks which are waiting in the current round
+    arrived: usize,
+    /// Incremented whenever a round is complete (wrapping)
+    generation: u32,
+}
+
+/// Lets `n` tasks wait for each other at a checkpoint, e.g. until all sensor tasks are initialised.
+///
+/// The first `n - 1` tasks which call [`wait`](Barrier::wait) wait in a `maitake_sync::WaitQueue`, and the `n`th one
+/// releases all of them together and becomes the leader. Then the barrier starts the next round, so it can be reused.
+/// A wait which is dropped before its round completes does not count as arrived anymore.
+pub struct Barrier<M = ThreadModeRawMutex> {
+    n: usize,
+    round: Mutex<M, Cell<Round>>,
+    waker_registration: WaitQueue,
+}
+
+impl<M: RawMutex> Barrier<M> {
+    /// Create a barrier for `n` tasks. A barrier for zero tasks behaves like one for a single task.
+    pub const fn new(n: usize) -> Self {
+        Self {
+            n: if n == 0 { 1 } else { n },
+            round: Mutex::new(Cell::new(Round {
+                arrived: 0,
+                generation: 0,
+            })),
+            waker_registration: WaitQueue::new(),
+        }
+    }
+
+    /// Number of tasks which are waiting in the current round.
+    pub fn arrived(&self) -> usize {
+        self.round.lock(|round| round.get().arrived)
+    }
+
+    /// Wait until `n` tasks arrived in the current round.
+    pub async fn wait(&self) -> BarrierWaitResult {
+        let (generation, leader) = self.round.lock(|round| {
+            let Round {
+                arrived,
+                generation,
+            } = round.get();
+
+            if arrived + 1 == self.n {
+                round.set(Round {
+                    arrived: 0,
+                    generation: generation.wrapping_add(1),
+                });
+                (generation, true)
+            } else {
+                round.set(Round {
+                    arrived: arrived + 1,
+                    generation,
+                });
+                (generation, false)
+            }
+        });
+
+        if leader {
+            self.waker_registration.wake_all();
+            return BarrierWaitResult { leader };
+        }
+
+        // A dropped wait leaves the round, unless the round completed in the meantime
+        let _leave = OnDrop::new(|| {
+            self.round.lock(|round| {
+                let mut current = round.get();
+                if current.generation == generation {
+                    current.arrived -= 1;
+                    round.set(current);
+                }
+            });
+        });
+
+        // The queue is never closed
+        let _ = self
+            .waker_registration
+            .wait_for(|| self.round.lock(|round| round.get().generation) != generation)
+            .await;
+
+        BarrierWaitResult { leader }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use core::cell::RefCell;
+    use core::pin::pin;
+    use core::task::{Context, Poll};
+
+    use super::*;
+    use crate::names::register_current_task;
+    use crate::sim::{RunOrder, Simulator};
+    use crate::test_util::{CountingWaker, in_thread_mode};
+    use crate::{ChangeSignal, State, WaitQueueSignal};
+
+    /// The three tasks of the `maitake_wait_queue` demo. TaskThree takes until `init` is set to initialise.
+    #[test]
+    fn test_releases_pool_together() {
+        in_thread_mode(|| {
+            let barrier: Barrier = Barrier::new(3);
+            let init: WaitQueueSignal = WaitQueueSignal::new();
+            let passed = RefCell::new(std::vec::Vec::new());
+
+            let task = |name: &'static str| {
+                let (barrier, init, passed) = (&barrier, &init, &passed);
+                async move {
+                    let _name = register_current_task(name).await;
+                    for round in 1..=2 {
+                        while name == "TaskThree" && init.get() != State::Ready(round) {
+                            let _ = init.wait_changed(&init.get()).await;
+                        }
+                        let result = barrier.wait().await;
+                        passed.borrow_mut().push((name, result.is_leader()));
+                    }
+                }
+            };
+
+            let mut sim = Simulator::new(RunOrder::Embassy);
+            sim.spawn("TaskTwo", task("TaskTwo"));
+            sim.spawn("TaskOne", task("TaskOne"));
+            sim.spawn("TaskThree", task("TaskThree"));
+
+            for round in 1..=2 {
+                // Nobody passes until the last task arrives
+                sim.run_for(10);
+                assert_eq!(barrier.arrived(), 2);
+                assert_eq!(passed.borrow().len(), 3 * (round as usize - 1));
+
+                init.set(State::Ready(round));
+                sim.run_for(10);
+
+                let passed = passed.borrow();
+                let this_round = &passed[3 * (round as usize - 1)..];
+                assert_eq!(this_round.len(), 3);
+                assert_eq!(
+                    this_round
+                        .iter()
+                        .filter(|(_, leader)| *leader)
+                        .collect::<std::vec::Vec<_>>(),
+                    [&("TaskThree", true)]
+                );
+            }
+
+            assert!(sim.tasks().iter().all(|task| task.completed));
+        });
+    }
+
+    #[test]
+    fn test_dropped_wait_leaves() {
+        in_thread_mode(|| {
+            let barrier: Barrier = Barrier::new(2);
+            let (counter, waker) = CountingWaker::new();
+            let mut cx = Context::from_waker(&waker);
+
+            {
+                let mut wait = pin!(barrier.wait());
+                assert_eq!(wait.as_mut().poll(&mut cx), Poll::Pending);
+                assert_eq!(barrier.arrived(), 1);
+            }
+            assert_eq!(barrier.arrived(), 0);
+
+            // So the next task still has to wait for a second one
+            let mut first = pin!(barrier.wait());
+            assert_eq!(first.as_mut().poll(&mut cx), Poll::Pending);
+
+            let second = pin!(barrier.wait());
+            assert_eq!(
+                second.poll(&mut cx),
+                Poll::Ready(BarrierWaitResult { leader: true })
+            );
+            assert_eq!(counter.wakes(), 1);
+            assert_eq!(
+                first.poll(&mut cx),
+                Poll::Ready(BarrierWaitResult { leader: false })
+            );
+        });
+    }
+}
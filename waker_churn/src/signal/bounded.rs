@@ -0,0 +1,444 @@
+use core::cell::{Cell, RefCell};
+use core::future::poll_fn;
+use core::task::{Poll, Waker};
+
+use embassy_sync::blocking_mutex::Mutex;
+use embassy_sync::blocking_mutex::raw::{RawMutex, ThreadModeRawMutex};
+use heapless::Vec;
+use maitake_sync::WaitQueue;
+
+use crate::names::task_name;
+use crate::signal::OnDrop;
+use crate::stats::ChurnProbe;
+use crate::{ChangeSignal, Closed, State, WakerId};
+
+/// What [`BoundedWakerRegistration::register`] does if all `N` slots are taken.
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OverflowPolicy {
+    /// Wake and remove all stored wakers, then store the new one. This is what `MultiWakerRegistration` does.
+    WakeAllRetry,
+    /// Don't store the new waker and return [`Overflow`]. The caller decides how the task makes progress.
+    Reject,
+    /// Wake and remove the waker which was stored first, then store the new one.
+    EvictOldest,
+}
+
+/// Error of [`BoundedWakerRegistration::register`] with [`OverflowPolicy::Reject`].
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Overflow;
+
+/// Error of [`BoundedSignal::try_wait_changed`].
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BoundedWaitError {
+    /// All `N` slots are taken and the policy is [`OverflowPolicy::Reject`], so the waker was not stored.
+    Overflow,
+    /// The signal was closed.
+    Closed,
+}
+
+impl From<Closed> for BoundedWaitError {
+    fn from(_: Closed) -> Self {
+        Self::Closed
+    }
+}
+
+/// A stored waker and the wait it belongs to.
+struct Waiter {
+    /// Identifies the wait, a task may wait several times at once, e.g. in a `select`
+    id: u32,
+    waker: Waker,
+}
+
+/// Stores up to `N` wakers like `MultiWakerRegistration`, but what happens to an additional one is chosen by an [`OverflowPolicy`].
+///
+/// Every registration which finds all slots taken counts as an overflow, so a waiter count which outgrew `N` shows up
+/// in [`overflows`](BoundedWakerRegistration::overflows) instead of only in the number of polls.
+/// Every wait takes its own slot, and the wakers are kept in the order they were registered.
+pub struct BoundedWakerRegistration<const N: usize> {
+    wakers: Vec<Waiter, N>,
+    next_id: u32,
+    policy: OverflowPolicy,
+    overflows: u32,
+}
+
+impl<const N: usize> BoundedWakerRegistration<N> {
+    pub const fn new(policy: OverflowPolicy) -> Self {
+        Self {
+            wakers: Vec::new(),
+            next_id: 0,
+            policy,
+            overflows: 0,
+        }
+    }
+
+    pub fn policy(&self) -> OverflowPolicy {
+        self.policy
+    }
+
+    /// Number of registrations which found all slots taken (wrapping).
+    pub fn overflows(&self) -> u32 {
+        self.overflows
+    }
+
+    /// Number of stored wakers.
+    pub fn len(&self) -> usize {
+        self.wakers.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.wakers.is_empty()
+    }
+
+    /// Store the waker of a wait and return the id of the wait. A wait which is still stored (`id`) keeps its slot.
+    /// `probe` learns which tasks an overflow wakes.
+    pub fn register(
+        &mut self,
+        id: Option<u32>,
+        waker: &Waker,
+        probe: &impl ChurnProbe,
+    ) -> Result<u32, Overflow> {
+        if let Some(stored) = self.wakers.iter_mut().find(|stored| Some(stored.id) == id) {
+            stored.waker.clone_from(waker);
+            return Ok(stored.id);
+        }
+
+        if self.wakers.is_full() {
+            self.overflows = self.overflows.wrapping_add(1);
+            match self.policy {
+                OverflowPolicy::WakeAllRetry => {
+                    for stored in self.wakers.iter() {
+                        probe.replaced(WakerId::of(&stored.waker));
+                    }
+                    self.wake(probe);
+                }
+                OverflowPolicy::Reject => return Err(Overflow),
+                OverflowPolicy::EvictOldest => {
+                    let oldest = self.wakers.remove(0);
+                    probe.replaced(WakerId::of(&oldest.waker));
+                    probe.woken(WakerId::of(&oldest.waker));
+                    oldest.waker.wake();
+                }
+            }
+        }
+
+        let id = self.next_id;
+        self.next_id = id.wrapping_add(1);
+        let _ = self.wakers.push(Waiter {
+            id,
+            waker: waker.clone(),
+        });
+        Ok(id)
+    }
+
+    /// Remove the waker of the wait `id` without waking it.
+    pub fn unregister(&mut self, id: u32) {
+        self.wakers.retain(|stored| stored.id != id);
+    }
+
+    /// Wake and remove all stored wakers.
+    pub fn wake(&mut self, probe: &impl ChurnProbe) {
+        for stored in self.wakers.iter() {
+            probe.woken(WakerId::of(&stored.waker));
+            stored.waker.wake_by_ref();
+        }
+        self.wakers.clear();
+    }
+}
+
+struct Inner<const N: usize, T> {
+    state: RefCell<T>,
+    waker_registration: RefCell<BoundedWakerRegistration<N>>,
+    closed: Cell<bool>,
+}
+
+/// Like [`MultiWakerRegistrationSignal`](crate::MultiWakerRegistrationSignal), but on a [`BoundedWakerRegistration`],
+/// so the behaviour of more than `N` waiters is chosen by the [`OverflowPolicy`].
+///
+/// With [`OverflowPolicy::Reject`], [`try_wait_changed`](BoundedSignal::try_wait_changed) returns
+/// [`BoundedWaitError::Overflow`] to the caller. `wait_changed` of [`ChangeSignal`] parks a rejected waiter in a
+/// `maitake_sync::WaitQueue` instead, which is woken together with the stored wakers, so it neither spins nor is lost.
+/// `M` protects the state and the wakers, see [`SingleSlotSignal`](crate::SingleSlotSignal).
+pub struct BoundedSignal<const N: usize, P = (), M = ThreadModeRawMutex, T = State> {
+    inner: Mutex<M, Inner<N, T>>,
+    /// Waiters of `wait_changed` whose waker was rejected
+    fallback: WaitQueue,
+    probe: P,
+}
+
+impl<const N: usize, M: RawMutex> BoundedSignal<N, (), M> {
+    pub const fn new(policy: OverflowPolicy) -> Self {
+        Self::with_probe(policy, ())
+    }
+}
+
+impl<const N: usize, P: ChurnProbe, M: RawMutex> BoundedSignal<N, P, M> {
+    /// Create a signal which reports its churn to `probe`.
+    pub const fn with_probe(policy: OverflowPolicy, probe: P) -> Self {
+        Self::with_initial(State::NotReady, policy, probe)
+    }
+}
+
+impl<const N: usize, P: ChurnProbe, M: RawMutex, T> BoundedSignal<N, P, M, T> {
+    /// Create a signal with a custom payload, starting at `initial`.
+    pub const fn with_initial(initial: T, policy: OverflowPolicy, probe: P) -> Self {
+        Self {
+            inner: Mutex::new(Inner {
+                state: RefCell::new(initial),
+                waker_registration: RefCell::new(BoundedWakerRegistration::new(policy)),
+                closed: Cell::new(false),
+            }),
+            fallback: WaitQueue::new(),
+            probe,
+        }
+    }
+
+    /// Number of registrations which found all `N` slots taken.
+    pub fn overflows(&self) -> u32 {
+        self.inner
+            .lock(|s| s.waker_registration.borrow().overflows())
+    }
+}
+
+impl<const N: usize, P: ChurnProbe, M: RawMutex, T: Clone + PartialEq> BoundedSignal<N, P, M, T> {
+    /// Like [`ChangeSignal::wait_changed`], but returns [`BoundedWaitError::Overflow`] if the waker was rejected,
+    /// so the caller decides how the task makes progress, e.g. by retrying later.
+    pub async fn try_wait_changed(&self, current: &T) -> Result<T, BoundedWaitError> {
+        let mut first_poll = true;
+        // The waiter this wait stored, so a dropped wait can remove it again
+        let registered = Cell::new(None);
+        let _unregister = OnDrop::new(|| {
+            if let Some(id) = registered.get() {
+                self.inner
+                    .lock(|s| s.waker_registration.borrow_mut().unregister(id));
+            }
+        });
+
+        poll_fn(|cx| {
+            let task = WakerId::of(cx.waker());
+            trace!("Running waker of task: {}", task_name(task));
+            let first = core::mem::replace(&mut first_poll, false);
+
+            self.inner.lock(|s| {
+                if s.closed.get() {
+                    registered.set(None);
+                    return Poll::Ready(Err(BoundedWaitError::Closed));
+                }
+
+                let state = s.state.borrow();
+                if *state != *current {
+                    // The waiter is still stored if the task was polled for another reason, e.g. another wait of a `select`
+                    if let Some(id) = registered.take() {
+                        s.waker_registration.borrow_mut().unregister(id);
+                    }
+                    self.probe.completed(task);
+                    return Poll::Ready(Ok(state.clone()));
+                }
+
+                trace!("{}: Signal not ready, registering waker", task_name(task));
+                if !first {
+                    self.probe.spurious_poll(task);
+                }
+                self.probe.registered(task);
+                let mut waker_registration = s.waker_registration.borrow_mut();
+                match waker_registration.register(registered.get(), cx.waker(), &self.probe) {
+                    Ok(id) => {
+                        registered.set(Some(id));
+                        Poll::Pending
+                    }
+                    Err(Overflow) => {
+                        warn!("{}: No free waker slot", task_name(task));
+                        registered.set(None);
+                        Poll::Ready(Err(BoundedWaitError::Overflow))
+                    }
+                }
+            })
+        })
+        .await
+    }
+}
+
+impl<const N: usize, P: ChurnProbe, M: RawMutex, T: Clone + PartialEq> ChangeSignal<T>
+    for BoundedSignal<N, P, M, T>
+{
+    fn with<R>(&self, f: impl FnOnce(&T) -> R) -> R {
+        self.inner.lock(|s| f(&s.state.borrow()))
+    }
+
+    fn set(&self, value: T) {
+        self.inner.lock(|s| {
+            *s.state.borrow_mut() = value;
+            s.waker_registration.borrow_mut().wake(&self.probe);
+        });
+        self.fallback.wake_all();
+    }
+
+    fn close(&self) {
+        self.inner.lock(|s| {
+            s.closed.set(true);
+            s.waker_registration.borrow_mut().wake(&self.probe);
+        });
+        self.fallback.close();
+    }
+
+    fn is_closed(&self) -> bool {
+        self.inner.lock(|s| s.closed.get())
+    }
+
+    /// A waiter whose waker is rejected waits in the fallback queue until the next `set` or `close`.
+    async fn wait_changed(&self, current: &T) -> Result<T, Closed> {
+        match self.try_wait_changed(current).await {
+            Ok(state) => Ok(state),
+            Err(BoundedWaitError::Closed) => Err(Closed),
+            // The state is returned from the same lock which found it changed
+            Err(BoundedWaitError::Overflow) => self
+                .fallback
+                .wait_for_value(|| self.with(|state| (state != current).then(|| state.clone())))
+                .await
+                .map_err(|_| Closed),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use core::pin::pin;
+    use core::task::Context;
+
+    use super::*;
+    use crate::sim::{RunOrder, Simulator};
+    use crate::test_util::{CountingWaker, in_thread_mode};
+    use crate::wait_for_signal;
+
+    const UPDATES: u32 = 4;
+    /// Ticks the tasks run before each update and after the last one
+    const TICKS: u64 = 20;
+    /// One poll to register, then one per update
+    const POLLS: u64 = 1 + UPDATES as u64;
+
+    const POLICIES: [OverflowPolicy; 3] = [
+        OverflowPolicy::WakeAllRetry,
+        OverflowPolicy::Reject,
+        OverflowPolicy::EvictOldest,
+    ];
+
+    /// Run the three tasks of the `maitake_wait_queue` demo and return the polls of each task and the overflows.
+    fn run_three_tasks<const N: usize>(policy: OverflowPolicy) -> (std::vec::Vec<u64>, u32) {
+        let signal: BoundedSignal<N> = BoundedSignal::new(policy);
+        let mut sim = Simulator::new(RunOrder::Embassy);
+        sim.spawn("TaskTwo", wait_for_signal("TaskTwo", &signal, true));
+        sim.spawn("TaskOne", wait_for_signal("TaskOne", &signal, false));
+        sim.spawn("TaskThree", wait_for_signal("TaskThree", &signal, true));
+
+        for counter in 1..=UPDATES {
+            sim.run_for(TICKS);
+            signal.set(State::Ready(counter));
+        }
+        sim.run_for(TICKS);
+
+        let polls = sim.tasks().iter().map(|task| task.polls).collect();
+        drop(sim);
+        (polls, signal.overflows())
+    }
+
+    #[test]
+    fn test_no_overflow_with_a_slot_per_task() {
+        in_thread_mode(|| {
+            for policy in POLICIES {
+                assert_eq!(run_three_tasks::<3>(policy), (vec![POLLS; 3], 0));
+            }
+        });
+    }
+
+    /// Two slots for three tasks: `WakeAllRetry` and `EvictOldest` wake a stored waiter for every overflow, which
+    /// registers again and overflows in turn, so the tasks are polled until the next update. A rejected waiter of
+    /// `Reject` waits in the fallback queue instead and is not polled more often.
+    #[test]
+    fn test_extra_polls_per_policy() {
+        in_thread_mode(|| {
+            for policy in POLICIES {
+                let (polls, overflows) = run_three_tasks::<2>(policy);
+                let extra: std::vec::Vec<u64> = polls.iter().map(|polls| polls - POLLS).collect();
+                assert!(overflows > 0, "{policy:?}");
+
+                if policy == OverflowPolicy::Reject {
+                    assert_eq!(extra, [0, 0, 0]);
+                } else {
+                    assert!(extra.iter().sum::<u64>() > 0, "{policy:?}: {extra:?}");
+                    // At most one poll per tick and task
+                    let ticks = TICKS * (UPDATES as u64 + 1);
+                    assert!(
+                        extra.iter().all(|&extra| extra <= ticks),
+                        "{policy:?}: {extra:?}"
+                    );
+                }
+            }
+        });
+    }
+
+    #[test]
+    fn test_evict_oldest_wakes_only_the_oldest() {
+        let wakers: [_; 3] = core::array::from_fn(|_| CountingWaker::new());
+        let mut registration = BoundedWakerRegistration::<2>::new(OverflowPolicy::EvictOldest);
+
+        for (_, waker) in &wakers {
+            assert!(registration.register(None, waker, &()).is_ok());
+        }
+
+        assert_eq!(registration.overflows(), 1);
+        assert_eq!(registration.len(), 2);
+        let wakes = wakers.each_ref().map(|(counter, _)| counter.wakes());
+        assert_eq!(wakes, [1, 0, 0]);
+    }
+
+    /// Two waits of one task take a slot each, so dropping one of them leaves the other one stored.
+    #[test]
+    fn test_dropped_wait_keeps_other_wait() {
+        in_thread_mode(|| {
+            for policy in POLICIES {
+                let signal: BoundedSignal<2> = BoundedSignal::new(policy);
+                let (counter, waker) = CountingWaker::new();
+                let mut cx = Context::from_waker(&waker);
+
+                let mut live = pin!(signal.wait_changed(&State::NotReady));
+                assert_eq!(live.as_mut().poll(&mut cx), Poll::Pending);
+                {
+                    let mut dropped = pin!(signal.wait_changed(&State::NotReady));
+                    assert_eq!(dropped.as_mut().poll(&mut cx), Poll::Pending);
+                }
+
+                signal.set(State::Ready(1));
+                assert_eq!(counter.wakes(), 1, "{policy:?}");
+                assert_eq!(live.poll(&mut cx), Poll::Ready(Ok(State::Ready(1))));
+                assert_eq!(signal.overflows(), 0);
+            }
+        });
+    }
+
+    #[test]
+    fn test_reject_is_returned() {
+        in_thread_mode(|| {
+            let signal: BoundedSignal<1> = BoundedSignal::new(OverflowPolicy::Reject);
+            let (counter, waker) = CountingWaker::new();
+            let (_, other_waker) = CountingWaker::new();
+            let mut cx = Context::from_waker(&waker);
+
+            let mut stored = pin!(signal.try_wait_changed(&State::NotReady));
+            assert_eq!(stored.as_mut().poll(&mut cx), Poll::Pending);
+
+            // The second task learns about the overflow instead of waking itself
+            let rejected = pin!(signal.try_wait_changed(&State::NotReady));
+            assert_eq!(
+                rejected.poll(&mut Context::from_waker(&other_waker)),
+                Poll::Ready(Err(BoundedWaitError::Overflow))
+            );
+            assert_eq!(signal.overflows(), 1);
+
+            signal.set(State::Ready(1));
+            assert_eq!(counter.wakes(), 1);
+            assert_eq!(stored.poll(&mut cx), Poll::Ready(Ok(State::Ready(1))));
+        });
+    }
+}
@@ -0,0 +1,292 @@
+use core::cell::RefCell;
+
+use embassy_sync::blocking_mutex::Mutex;
+use embassy_sync::blocking_mutex::raw::{RawMutex, ThreadModeRawMutex};
+use maitake_sync::WaitQueue;
+
+use crate::names::register_current_task;
+use crate::{Closed, State};
+
+/// Result of [`Subscriber::next`].
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Received<T = State> {
+    /// The next published state
+    Value(T),
+    /// The subscriber fell behind and this many states were overwritten before it read them.
+    /// The next call continues with the oldest state which is still buffered.
+    Lagged(u32),
+}
+
+struct Inner<const CAP: usize, T> {
+    /// The last `CAP` published states, `None` until the slot is written the first time
+    buffer: [Option<T>; CAP],
+    /// Index of the next state in `buffer`
+    head: usize,
+    /// Number of states published so far (wrapping), i.e. the sequence number of the next one
+    written: u32,
+}
+
+/// Bounded broadcast of every published state.
+///
+/// The signals only keep the latest [`State`], so a waiter which is not polled in time never sees the states in between.
+/// `Broadcast` keeps the last `CAP` states in a ring buffer instead, and every [`Subscriber`] reads all of them in order.
+/// A subscriber which falls more than `CAP` states behind gets [`Received::Lagged`] with the exact number of states it lost.
+/// The publisher never waits, like the `ImmediatePublisher` of `embassy_sync`'s `PubSubChannel`.
+///
+/// The subscribers wait in a `maitake_sync::WaitQueue` and only store their read position,
+/// so unlike `PubSubChannel` the number of subscribers does not need to be specified upfront.
+/// `T` is the payload, see [`ChangeSignal`](crate::ChangeSignal). `CAP` must not be zero, which is checked at compile time.
+pub struct Broadcast<const CAP: usize, M = ThreadModeRawMutex, T = State> {
+    inner: Mutex<M, RefCell<Inner<CAP, T>>>,
+    waker_registration: WaitQueue,
+}
+
+impl<const CAP: usize, M: RawMutex, T: Clone> Broadcast<CAP, M, T> {
+    pub const fn new() -> Self {
+        const {
+            assert!(
+                CAP > 0,
+                "a broadcast needs a capacity of at least one state"
+            )
+        };
+
+        Self {
+            inner: Mutex::new(RefCell::new(Inner {
+                buffer: [const { None }; CAP],
+                head: 0,
+                written: 0,
+            })),
+            waker_registration: WaitQueue::new(),
+        }
+    }
+
+    /// Append a state, overwriting the oldest one if the buffer is full, and wake all subscribers.
+    pub fn publish(&self, state: T) {
+        self.inner.lock(|inner| {
+            let mut inner = inner.borrow_mut();
+            let head = inner.head;
+            inner.buffer[head] = Some(state);
+            inner.head = (head + 1) % CAP;
+            inner.written = inner.written.wrapping_add(1);
+        });
+        self.waker_registration.wake_all();
+    }
+
+    /// Number of states published so far (wrapping).
+    pub fn written(&self) -> u32 {
+        self.inner.lock(|inner| inner.borrow().written)
+    }
+
+    /// Create a subscriber which receives the states published from now on.
+    pub fn subscriber(&self) -> Subscriber<'_, CAP, M, T> {
+        Subscriber {
+            broadcast: self,
+            next: self.written(),
+        }
+    }
+
+    /// Close the broadcast. The subscribers still receive the buffered states, then [`Closed`].
+    pub fn close(&self) {
+        self.waker_registration.close();
+    }
+
+    pub fn is_closed(&self) -> bool {
+        self.waker_registration.is_closed()
+    }
+}
+
+impl<const CAP: usize, M: RawMutex, T: Clone> Default for Broadcast<CAP, M, T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Reads the states of a [`Broadcast`] in the order they were published.
+pub struct Subscriber<'a, const CAP: usize, M: RawMutex = ThreadModeRawMutex, T = State> {
+    broadcast: &'a Broadcast<CAP, M, T>,
+    /// Sequence number of the next state to read
+    next: u32,
+}
+
+impl<const CAP: usize, M: RawMutex, T: Clone> Subscriber<'_, CAP, M, T> {
+    /// Number of published states this subscriber did not read yet, including the lost ones.
+    pub fn available(&self) -> u32 {
+        self.broadcast.written().wrapping_sub(self.next)
+    }
+
+    /// Read the next state without waiting. Returns `None` if the subscriber is up to date.
+    pub fn try_next(&mut self) -> Option<Received<T>> {
+        self.broadcast.inner.lock(|inner| {
+            let inner = inner.borrow();
+            let behind = inner.written.wrapping_sub(self.next);
+
+            if behind == 0 {
+                None
+            } else if behind as usize > CAP {
+                let lost = behind - CAP as u32;
+                self.next = self.next.wrapping_add(lost);
+                Some(Received::Lagged(lost))
+            } else {
+                let state = inner.buffer[(inner.head + CAP - behind as usize) % CAP]
+                    .clone()
+                    .expect("the last `written` slots are written");
+                self.next = self.next.wrapping_add(1);
+                Some(Received::Value(state))
+            }
+        })
+    }
+
+    /// Wait for the next state. Returns [`Closed`] once the broadcast is closed and all buffered states were read.
+    pub async fn next(&mut self) -> Result<Received<T>, Closed> {
+        // A closed queue does not check the condition anymore, so drain the buffer first
+        if let Some(received) = self.try_next() {
+            return Ok(received);
+        }
+
+        let queue = &self.broadcast.waker_registration;
+        queue
+            .wait_for_value(|| self.try_next())
+            .await
+            .map_err(|_| Closed)
+    }
+}
+
+/// Task body like [`wait_for_signal`](crate::wait_for_signal), but the task sees every odd or even state
+/// and warns about the states it lost.
+pub async fn subscribe_broadcast<const CAP: usize, M: RawMutex>(
+    name: &'static str,
+    broadcast: &Broadcast<CAP, M>,
+    odd: bool,
+) {
+    info!("Starting {} task", name);
+    let _name = register_current_task(name).await;
+
+    let mut subscriber = broadcast.subscriber();
+
+    loop {
+        match subscriber.next().await {
+            Ok(Received::Value(state)) => match (odd, state) {
+                (true, State::Ready(x)) if x % 2 == 1 => {
+                    info!("{}: Odd state: {:?}", name, state);
+                }
+                (false, State::Ready(x)) if x % 2 == 0 => {
+                    info!("{}: Even state: {:?}", name, state);
+                }
+                _ => {}
+            },
+            Ok(Received::Lagged(lost)) => {
+                warn!("{}: Lost {} states", name, lost);
+            }
+            Err(Closed) => {
+                info!("{}: Signal closed", name);
+                return;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use core::pin::pin;
+    use core::task::{Context, Poll};
+
+    use super::*;
+    use crate::test_util::{CountingWaker, in_thread_mode};
+
+    #[test]
+    fn test_every_value_in_order() {
+        in_thread_mode(|| {
+            let broadcast: Broadcast<4> = Broadcast::new();
+            let mut fast = broadcast.subscriber();
+            let mut slow = broadcast.subscriber();
+
+            for x in 1..=3 {
+                broadcast.publish(State::Ready(x));
+                assert_eq!(fast.try_next(), Some(Received::Value(State::Ready(x))));
+            }
+            assert_eq!(fast.try_next(), None);
+
+            // The slow subscriber still gets every value, because it is within the capacity
+            assert_eq!(slow.available(), 3);
+            for x in 1..=3 {
+                assert_eq!(slow.try_next(), Some(Received::Value(State::Ready(x))));
+            }
+            assert_eq!(slow.try_next(), None);
+        });
+    }
+
+    #[test]
+    fn test_lag_is_exact() {
+        in_thread_mode(|| {
+            let broadcast: Broadcast<4> = Broadcast::new();
+            let mut subscriber = broadcast.subscriber();
+
+            for x in 1..=10 {
+                broadcast.publish(State::Ready(x));
+            }
+
+            // 1..=6 were overwritten, 7..=10 are still buffered
+            assert_eq!(subscriber.try_next(), Some(Received::Lagged(6)));
+            for x in 7..=10 {
+                assert_eq!(
+                    subscriber.try_next(),
+                    Some(Received::Value(State::Ready(x)))
+                );
+            }
+            assert_eq!(subscriber.try_next(), None);
+        });
+    }
+
+    #[test]
+    fn test_wakes_and_closes() {
+        in_thread_mode(|| {
+            let broadcast: Broadcast<4> = Broadcast::new();
+            let mut subscriber = broadcast.subscriber();
+            let (counter, waker) = CountingWaker::new();
+            let mut cx = Context::from_waker(&waker);
+
+            {
+                let mut next = pin!(subscriber.next());
+                assert_eq!(next.as_mut().poll(&mut cx), Poll::Pending);
+
+                broadcast.publish(State::Ready(1));
+                assert_eq!(counter.wakes(), 1);
+                assert_eq!(
+                    next.poll(&mut cx),
+                    Poll::Ready(Ok(Received::Value(State::Ready(1))))
+                );
+            }
+
+            // Buffered states are still received after the close
+            broadcast.publish(State::Ready(2));
+            broadcast.close();
+            {
+                let next = pin!(subscriber.next());
+                assert_eq!(
+                    next.poll(&mut cx),
+                    Poll::Ready(Ok(Received::Value(State::Ready(2))))
+                );
+            }
+            let next = pin!(subscriber.next());
+            assert_eq!(next.poll(&mut cx), Poll::Ready(Err(Closed)));
+        });
+    }
+
+    #[test]
+    fn test_custom_payload() {
+        in_thread_mode(|| {
+            let broadcast: Broadcast<2, ThreadModeRawMutex, heapless::String<8>> = Broadcast::new();
+            let mut subscriber = broadcast.subscriber();
+
+            for name in ["one", "two", "three"] {
+                broadcast.publish(name.try_into().unwrap());
+            }
+            assert_eq!(subscriber.try_next(), Some(Received::Lagged(1)));
+            assert_eq!(
+                subscriber.try_next(),
+                Some(Received::Value("two".try_into().unwrap()))
+            );
+        });
+    }
+}
@@ -0,0 +1,337 @@
+use core::cell::{Cell, RefCell};
+use core::future::poll_fn;
+use core::task::{Poll, Waker};
+
+use embassy_sync::blocking_mutex::Mutex;
+use embassy_sync::blocking_mutex::raw::{RawMutex, ThreadModeRawMutex};
+use embassy_time::{Duration, Instant};
+use heapless::Vec;
+
+use crate::names::task_name;
+use crate::signal::OnDrop;
+use crate::{ChangeSignal, Closed, State, WakerId};
+
+/// A stored waker and the wait it belongs to.
+struct Waiter {
+    /// Identifies the wait, a task may wait several times at once, e.g. in a `select`
+    id: u32,
+    waker: Waker,
+}
+
+struct Inner<const N: usize, T> {
+    /// The value the waiters see
+    published: T,
+    /// The latest value of the open window, published when the window ends
+    pending: Option<T>,
+    /// End of the open window
+    deadline: Instant,
+    /// Wakers of the waits, in the order they registered
+    waiters: Vec<Waiter, N>,
+    next_id: u32,
+    closed: bool,
+}
+
+impl<const N: usize, T> Inner<N, T> {
+    /// Publish the value of the window if it ended.
+    fn flush(&mut self) {
+        if self.pending.is_some() && Instant::now() >= self.deadline {
+            self.published = self.pending.take().unwrap();
+        }
+    }
+
+    fn unregister(&mut self, id: u32) {
+        self.waiters.retain(|waiter| waiter.id != id);
+    }
+
+    fn wake_all(&mut self) {
+        while let Some(waiter) = self.waiters.pop() {
+            waiter.waker.wake();
+        }
+    }
+
+    /// Store the waker of a wait and return the id of the wait. A wait which is still stored (`id`) keeps its place.
+    fn register(&mut self, id: Option<u32>, waker: &Waker) -> u32 {
+        if let Some(waiter) = self.waiters.iter_mut().find(|waiter| Some(waiter.id) == id) {
+            waiter.waker.clone_from(waker);
+            return waiter.id;
+        }
+
+        if self.waiters.is_full() {
+            self.wake_all();
+        }
+        let id = self.next_id;
+        self.next_id = id.wrapping_add(1);
+        let _ = self.waiters.push(Waiter {
+            id,
+            waker: waker.clone(),
+        });
+        id
+    }
+}
+
+/// Signal which merges the updates of a window, so a producer which sets the state in a tight burst
+/// does not make the waiters churn through every intermediate value.
+///
+/// The first `set` after a quiet period opens a window of `window`. Further updates within the window only replace
+/// the value, and when it ends the last value is published. The waiters are not woken by `set` but by the timer queue
+/// of `embassy_time` at the end of the window, so each one is woken at most once per window, also under a continuous burst.
+/// Unlike a debounce which restarts the window on every update, a value is published at most `window` after it was set.
+///
+/// `get`, `with` and `wait_changed` only see published values. Up to `N` waiters are stored, and if more tasks register,
+/// all stored wakers are woken to make room, like `MultiWakerRegistration`. A dropped wait removes its waker, but a wake
+/// which is already scheduled in the timer queue can't be taken back, so its task may be polled once more.
+/// `M` protects the state and the waiters, see [`SingleSlotSignal`](crate::SingleSlotSignal).
+pub struct CoalescingSignal<const N: usize, M = ThreadModeRawMutex, T = State> {
+    inner: Mutex<M, RefCell<Inner<N, T>>>,
+    window: Duration,
+}
+
+impl<const N: usize, M: RawMutex> CoalescingSignal<N, M> {
+    /// Create a signal which publishes the updates of each `window`.
+    pub const fn new(window: Duration) -> Self {
+        Self::with_initial(State::NotReady, window)
+    }
+}
+
+impl<const N: usize, M: RawMutex, T> CoalescingSignal<N, M, T> {
+    /// Create a signal with a custom payload, starting at `initial`.
+    pub const fn with_initial(initial: T, window: Duration) -> Self {
+        Self {
+            inner: Mutex::new(RefCell::new(Inner {
+                published: initial,
+                pending: None,
+                deadline: Instant::from_ticks(0),
+                waiters: Vec::new(),
+                next_id: 0,
+                closed: false,
+            })),
+            window,
+        }
+    }
+
+    /// The length of a window.
+    pub fn window(&self) -> Duration {
+        self.window
+    }
+
+    /// True while a window is open, i.e. an update was set but not published yet.
+    pub fn is_pending(&self) -> bool {
+        self.inner.lock(|inner| {
+            let mut inner = inner.borrow_mut();
+            inner.flush();
+            inner.pending.is_some()
+        })
+    }
+}
+
+impl<const N: usize, M: RawMutex, T: Clone + PartialEq> ChangeSignal<T>
+    for CoalescingSignal<N, M, T>
+{
+    fn with<R>(&self, f: impl FnOnce(&T) -> R) -> R {
+        self.inner.lock(|inner| {
+            let mut inner = inner.borrow_mut();
+            inner.flush();
+            f(&inner.published)
+        })
+    }
+
+    /// Opens a window if none is open, and schedules the wakes of the stored waiters at its end.
+    fn set(&self, value: T) {
+        self.inner.lock(|inner| {
+            let mut inner = inner.borrow_mut();
+            inner.flush();
+
+            if inner.pending.is_none() {
+                inner.deadline = Instant::now() + self.window;
+                for waiter in inner.waiters.iter() {
+                    embassy_time_driver::schedule_wake(inner.deadline.as_ticks(), &waiter.waker);
+                }
+            }
+            inner.pending = Some(value);
+        });
+    }
+
+    /// Wakes all waiters right away, without waiting for the end of the window.
+    fn close(&self) {
+        self.inner.lock(|inner| {
+            let mut inner = inner.borrow_mut();
+            inner.closed = true;
+            inner.wake_all();
+        });
+    }
+
+    fn is_closed(&self) -> bool {
+        self.inner.lock(|inner| inner.borrow().closed)
+    }
+
+    async fn wait_changed(&self, current: &T) -> Result<T, Closed> {
+        // The waiter this wait stored, so a dropped wait can remove it again
+        let registered = Cell::new(None);
+        let _unregister = OnDrop::new(|| {
+            if let Some(id) = registered.get() {
+                self.inner.lock(|inner| inner.borrow_mut().unregister(id));
+            }
+        });
+
+        poll_fn(|cx| {
+            let task = WakerId::of(cx.waker());
+            trace!("Running waker of task: {}", task_name(task));
+
+            self.inner.lock(|inner| {
+                let mut inner = inner.borrow_mut();
+                inner.flush();
+
+                if inner.closed || inner.published != *current {
+                    if let Some(id) = registered.take() {
+                        inner.unregister(id);
+                    }
+                    return Poll::Ready(if inner.closed {
+                        Err(Closed)
+                    } else {
+                        Ok(inner.published.clone())
+                    });
+                }
+
+                trace!(
+                    "{}: No update published, registering waker",
+                    task_name(task)
+                );
+                let id = inner.register(registered.get(), cx.waker());
+                registered.set(Some(id));
+
+                // A task which starts waiting while a window is open is woken at its end as well
+                if inner.pending.is_some() {
+                    embassy_time_driver::schedule_wake(inner.deadline.as_ticks(), cx.waker());
+                }
+                Poll::Pending
+            })
+        })
+        .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use core::pin::pin;
+    use core::task::Context;
+
+    use super::*;
+    use crate::test_util::{CountingWaker, in_thread_mode, with_mock_time};
+
+    const WINDOW: Duration = Duration::from_millis(10);
+
+    #[test]
+    fn test_burst_is_merged() {
+        in_thread_mode(|| {
+            with_mock_time(|time| {
+                let signal: CoalescingSignal<2> = CoalescingSignal::new(WINDOW);
+                let (counter, waker) = CountingWaker::new();
+                let mut cx = Context::from_waker(&waker);
+
+                let mut wait = pin!(signal.wait_changed(&State::NotReady));
+                assert_eq!(wait.as_mut().poll(&mut cx), Poll::Pending);
+
+                // A burst within the window neither wakes the waiter nor is visible yet
+                for x in 1..=5 {
+                    signal.set(State::Ready(x));
+                    time.advance(Duration::from_millis(1));
+                }
+                assert_eq!(counter.wakes(), 0);
+                assert_eq!(signal.get(), State::NotReady);
+                assert!(signal.is_pending());
+
+                // At the end of the window the last value is published with a single wake
+                time.advance(WINDOW);
+                assert_eq!(counter.wakes(), 1);
+                assert!(!signal.is_pending());
+                assert_eq!(wait.poll(&mut cx), Poll::Ready(Ok(State::Ready(5))));
+            });
+        });
+    }
+
+    #[test]
+    fn test_one_wake_per_window() {
+        const WINDOWS: u32 = 4;
+        const UPDATES_PER_WINDOW: u32 = 5;
+
+        in_thread_mode(|| {
+            with_mock_time(|time| {
+                let signal: CoalescingSignal<2> = CoalescingSignal::new(WINDOW);
+                let waiters: [_; 2] = core::array::from_fn(|_| CountingWaker::new());
+                let mut current = [State::NotReady; 2];
+                let mut counter = 0;
+
+                for window in 1..=WINDOWS {
+                    let mut waits: [_; 2] = core::array::from_fn(|i| {
+                        let wait = Box::pin(signal.wait_changed(&current[i]));
+                        (wait, Context::from_waker(&waiters[i].1))
+                    });
+                    for (wait, cx) in waits.iter_mut() {
+                        assert_eq!(wait.as_mut().poll(cx), Poll::Pending);
+                    }
+
+                    // A continuous burst, one update per millisecond
+                    for _ in 0..UPDATES_PER_WINDOW {
+                        counter += 1;
+                        signal.set(State::Ready(counter));
+                        time.advance(Duration::from_millis(1));
+                    }
+                    time.advance(WINDOW);
+
+                    for (i, (wait, cx)) in waits.iter_mut().enumerate() {
+                        assert_eq!(waiters[i].0.wakes() as u32, window);
+                        let Poll::Ready(Ok(state)) = wait.as_mut().poll(cx) else {
+                            panic!("waiter {i} not ready after window {window}");
+                        };
+                        assert_eq!(state, State::Ready(counter));
+                    }
+                    drop(waits);
+                    current = [State::Ready(counter); 2];
+                }
+            });
+        });
+    }
+
+    /// Dropping one of two waits of a task, e.g. the other branch of a `select`, leaves the waker of the live one.
+    #[test]
+    fn test_dropped_wait_keeps_other_wait() {
+        in_thread_mode(|| {
+            with_mock_time(|time| {
+                let signal: CoalescingSignal<2> = CoalescingSignal::new(WINDOW);
+                let (counter, waker) = CountingWaker::new();
+                let mut cx = Context::from_waker(&waker);
+
+                let mut live = pin!(signal.wait_changed(&State::NotReady));
+                assert_eq!(live.as_mut().poll(&mut cx), Poll::Pending);
+                {
+                    let mut dropped = pin!(signal.wait_changed(&State::NotReady));
+                    assert_eq!(dropped.as_mut().poll(&mut cx), Poll::Pending);
+                }
+
+                signal.set(State::Ready(1));
+                time.advance(WINDOW);
+                assert_eq!(counter.wakes(), 1);
+                assert_eq!(live.poll(&mut cx), Poll::Ready(Ok(State::Ready(1))));
+            });
+        });
+    }
+
+    #[test]
+    fn test_close_does_not_wait_for_window() {
+        in_thread_mode(|| {
+            with_mock_time(|_| {
+                let signal: CoalescingSignal<1> = CoalescingSignal::new(WINDOW);
+                let (counter, waker) = CountingWaker::new();
+                let mut cx = Context::from_waker(&waker);
+
+                let mut wait = pin!(signal.wait_changed(&State::NotReady));
+                assert_eq!(wait.as_mut().poll(&mut cx), Poll::Pending);
+
+                signal.set(State::Ready(1));
+                signal.close();
+                assert_eq!(counter.wakes(), 1);
+                assert_eq!(wait.poll(&mut cx), Poll::Ready(Err(Closed)));
+            });
+        });
+    }
+}
@@ -0,0 +1,265 @@
+use core::cell::Cell;
+
+use embassy_sync::blocking_mutex::raw::RawMutex;
+use embassy_sync::blocking_mutex::{Mutex, ThreadModeMutex};
+use maitake_sync::WaitQueue;
+
+use crate::State;
+use crate::names::register_current_task;
+
+/// The blocking mutexes of `embassy_sync`. `ThreadModeMutex` is a type of its own, not a `Mutex` with a raw mutex.
+pub trait BlockingMutex {
+    type Data;
+
+    /// Lock the mutex and access the data, like the inherent `lock` of the mutexes.
+    fn lock<U>(&self, f: impl FnOnce(&Self::Data) -> U) -> U;
+}
+
+impl<R: RawMutex, T> BlockingMutex for Mutex<R, T> {
+    type Data = T;
+
+    fn lock<U>(&self, f: impl FnOnce(&T) -> U) -> U {
+        Mutex::lock(self, f)
+    }
+}
+
+impl<T> BlockingMutex for ThreadModeMutex<T> {
+    type Data = T;
+
+    fn lock<U>(&self, f: impl FnOnce(&T) -> U) -> U {
+        ThreadModeMutex::lock(self, f)
+    }
+}
+
+/// Async condition variable for the blocking mutexes of `embassy_sync`, e.g. a `ThreadModeMutex` or a `Mutex<CriticalSectionRawMutex, _>`.
+///
+/// The state lives in the mutex, and [`wait_while`](Condvar::wait_while) checks the condition under its lock,
+/// so application code never writes the `poll_fn` leaf future which registers a waker by hand.
+/// The waiters are stored in a `maitake_sync::WaitQueue`, so their number does not need to be specified upfront,
+/// and a dropped wait removes its waker.
+pub struct Condvar {
+    waker_registration: WaitQueue,
+}
+
+impl Condvar {
+    pub const fn new() -> Self {
+        Self {
+            waker_registration: WaitQueue::new(),
+        }
+    }
+
+    /// Wait as long as `condition` returns true for the state in `mutex`, then return what `map` reads from the state.
+    ///
+    /// The condition is checked right away and again whenever the task is notified, each time under the lock.
+    /// `map` runs under the same lock as the check which ended the wait, so it sees the state the condition was checked on,
+    /// even if e.g. an interrupt changes it right after (with a `Mutex<CriticalSectionRawMutex, _>`).
+    /// The task which changes the state has to call [`notify_one`](Condvar::notify_one) or
+    /// [`notify_all`](Condvar::notify_all) afterwards.
+    pub async fn wait_while<B: BlockingMutex, R>(
+        &self,
+        mutex: &B,
+        mut condition: impl FnMut(&B::Data) -> bool,
+        map: impl FnOnce(&B::Data) -> R,
+    ) -> R {
+        let mut map = Some(map);
+
+        let value = self
+            .waker_registration
+            .wait_for_value(|| {
+                mutex.lock(|state| {
+                    if condition(state) {
+                        None
+                    } else {
+                        map.take().map(|map| map(state))
+                    }
+                })
+            })
+            .await;
+        // The queue is never closed
+        value.unwrap_or_else(|_| unreachable!())
+    }
+
+    /// Wake the task which waits the longest. If no task waits, the next one which would wait checks its condition again.
+    pub fn notify_one(&self) {
+        self.waker_registration.wake();
+    }
+
+    /// Wake all waiting tasks.
+    pub fn notify_all(&self) {
+        self.waker_registration.wake_all();
+    }
+}
+
+impl Default for Condvar {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Task body like [`wait_for_signal`](crate::wait_for_signal), but the state is in a blocking mutex paired with a [`Condvar`].
+pub async fn wait_for_condvar<B: BlockingMutex<Data = Cell<State>>>(
+    name: &'static str,
+    state: &B,
+    condvar: &Condvar,
+    odd: bool,
+) {
+    info!("Starting {} task", name);
+    let _name = register_current_task(name).await;
+
+    let mut current_state = state.lock(Cell::get);
+
+    loop {
+        match (odd, current_state) {
+            (true, State::Ready(x)) if x % 2 == 1 => {
+                info!("{}: Odd state: {:?}", name, current_state);
+            }
+            (false, State::Ready(x)) if x % 2 == 0 => {
+                info!("{}: Even state: {:?}", name, current_state);
+            }
+            _ => {}
+        }
+
+        let current = current_state;
+        current_state = condvar
+            .wait_while(state, |s| s.get() == current, Cell::get)
+            .await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use core::cell::RefCell;
+
+    use embassy_sync::blocking_mutex::ThreadModeMutex;
+
+    use super::*;
+    use crate::sim::{RunOrder, Simulator};
+    use crate::test_util::in_thread_mode;
+
+    const UPDATES: u32 = 8;
+
+    /// The odd/even demo, with the state in a `ThreadModeMutex` instead of a signal.
+    #[test]
+    fn test_odd_even_demo() {
+        in_thread_mode(|| {
+            let state: ThreadModeMutex<Cell<State>> =
+                ThreadModeMutex::new(Cell::new(State::NotReady));
+            let condvar = Condvar::new();
+            let seen = RefCell::new(std::vec::Vec::new());
+
+            let task = |name: &'static str, odd: bool| {
+                let (state, condvar, seen) = (&state, &condvar, &seen);
+                async move {
+                    let _name = register_current_task(name).await;
+                    let mut current = State::NotReady;
+                    loop {
+                        let last = current;
+                        current = condvar
+                            .wait_while(state, |s| s.get() == last, Cell::get)
+                            .await;
+                        match current {
+                            State::Ready(x) if x % 2 == 1 && odd => seen.borrow_mut().push(x),
+                            State::Ready(x) if x % 2 == 0 && !odd => seen.borrow_mut().push(x),
+                            _ => {}
+                        }
+                    }
+                }
+            };
+
+            let mut sim = Simulator::new(RunOrder::Embassy);
+            sim.spawn("TaskTwo", task("TaskTwo", true));
+            sim.spawn("TaskOne", task("TaskOne", false));
+
+            for counter in 1..=UPDATES {
+                sim.run_for(10);
+                state.lock(|s| s.set(State::Ready(counter)));
+                condvar.notify_all();
+            }
+            sim.run_for(10);
+
+            // Like `WaitQueueSignal`: both tasks see every update, with one poll each
+            assert_eq!(sim.total_polls(), 2 + 2 * UPDATES as u64);
+            drop(sim);
+            let mut seen = seen.into_inner();
+            seen.sort();
+            assert_eq!(seen, (1..=UPDATES).collect::<std::vec::Vec<_>>());
+        });
+    }
+
+    #[test]
+    fn test_wait_for_condvar() {
+        in_thread_mode(|| {
+            let state: ThreadModeMutex<Cell<State>> =
+                ThreadModeMutex::new(Cell::new(State::NotReady));
+            let condvar = Condvar::new();
+
+            let mut sim = Simulator::new(RunOrder::Embassy);
+            sim.spawn(
+                "TaskTwo",
+                wait_for_condvar("TaskTwo", &state, &condvar, true),
+            );
+            sim.spawn(
+                "TaskOne",
+                wait_for_condvar("TaskOne", &state, &condvar, false),
+            );
+
+            for counter in 1..=UPDATES {
+                sim.run_for(10);
+                state.lock(|s| s.set(State::Ready(counter)));
+                condvar.notify_all();
+            }
+            sim.run_for(10);
+
+            assert_eq!(sim.total_polls(), 2 + 2 * UPDATES as u64);
+        });
+    }
+
+    /// The worker pool of `work_on_signal`, with the job in a `ThreadModeMutex`.
+    #[test]
+    fn test_notify_one_hands_off() {
+        in_thread_mode(|| {
+            let job: ThreadModeMutex<Cell<Option<u32>>> = ThreadModeMutex::new(Cell::new(None));
+            let condvar = Condvar::new();
+            let turns = RefCell::new(std::vec::Vec::new());
+
+            let worker = |name: &'static str| {
+                let (job, condvar, turns) = (&job, &condvar, &turns);
+                async move {
+                    loop {
+                        let job = condvar
+                            .wait_while(job, |j| j.get().is_none(), Cell::take)
+                            .await;
+                        turns.borrow_mut().push((name, job.unwrap()));
+                    }
+                }
+            };
+
+            let mut sim = Simulator::new(RunOrder::Embassy);
+            sim.spawn("TaskTwo", worker("TaskTwo"));
+            sim.spawn("TaskOne", worker("TaskOne"));
+            sim.spawn("TaskThree", worker("TaskThree"));
+
+            for counter in 1..=UPDATES {
+                sim.run_for(10);
+                job.lock(|j| j.set(Some(counter)));
+                condvar.notify_one();
+            }
+            sim.run_for(10);
+
+            // Every job is taken once, by the workers in turns, and only the worker which takes it is polled
+            assert_eq!(sim.total_polls(), 3 + UPDATES as u64);
+            drop(sim);
+            let turns = turns.into_inner();
+            assert_eq!(
+                turns
+                    .iter()
+                    .map(|(_, job)| *job)
+                    .collect::<std::vec::Vec<_>>(),
+                (1..=UPDATES).collect::<std::vec::Vec<_>>()
+            );
+            for (i, (name, _)) in turns.iter().enumerate() {
+                assert_eq!(*name, turns[i % 3].0, "{turns:?}");
+            }
+        });
+    }
+}
@@ -0,0 +1,437 @@
+use core::cell::{Cell, RefCell};
+use core::future::poll_fn;
+use core::task::{Poll, Waker};
+
+use embassy_sync::blocking_mutex::Mutex;
+use embassy_sync::blocking_mutex::raw::{RawMutex, ThreadModeRawMutex};
+use heapless::Vec;
+
+use crate::WakerId;
+use crate::names::{register_current_task, task_name};
+use crate::signal::OnDrop;
+
+/// The flag bits of an [`EventGroup`].
+pub type EventBits = u32;
+
+/// Bit of the odd states in the odd/even demo.
+pub const ODD_BIT: EventBits = 1 << 0;
+/// Bit of the even states in the odd/even demo.
+pub const EVEN_BIT: EventBits = 1 << 1;
+
+/// A stored waker and the bits it waits for.
+struct Waiter {
+    /// Identifies the wait, a task may wait several times at once, e.g. in a `select`
+    id: u32,
+    waker: Waker,
+    mask: EventBits,
+    /// Wait for all bits of `mask` instead of any of them
+    all: bool,
+    clear_on_exit: bool,
+    /// The bits which released the waiter, until its wait takes them
+    released: Option<EventBits>,
+}
+
+impl Waiter {
+    fn matches(&self, bits: EventBits) -> bool {
+        matches_mask(bits, self.mask, self.all)
+    }
+}
+
+fn matches_mask(bits: EventBits, mask: EventBits, all: bool) -> bool {
+    if all {
+        bits & mask == mask
+    } else {
+        bits & mask != 0
+    }
+}
+
+struct Inner<const N: usize> {
+    bits: EventBits,
+    waiters: Vec<Waiter, N>,
+    next_id: u32,
+}
+
+impl<const N: usize> Inner<N> {
+    fn position(&self, id: u32) -> Option<usize> {
+        self.waiters.iter().position(|waiter| waiter.id == id)
+    }
+
+    /// Release the waiters which match the bits, then clear the bits of the released `clear_on_exit` waiters.
+    fn release(&mut self) {
+        let current = self.bits;
+        let mut clear = 0;
+        for waiter in self.waiters.iter_mut() {
+            if waiter.released.is_none() && waiter.matches(current) {
+                waiter.released = Some(current);
+                if waiter.clear_on_exit {
+                    clear |= waiter.mask;
+                }
+                waiter.waker.wake_by_ref();
+            }
+        }
+
+        self.bits &= !clear;
+    }
+
+    /// Remove the waiter of a dropped wait without waking it, and give back the bits it cleared but never took.
+    fn unregister(&mut self, id: u32) {
+        let Some(i) = self.position(id) else {
+            return;
+        };
+
+        let waiter = self.waiters.remove(i);
+        if let Some(released) = waiter.released
+            && waiter.clear_on_exit
+        {
+            self.bits |= waiter.mask & released;
+            // The event goes to the next waiter instead of being lost
+            self.release();
+        }
+    }
+
+    /// Wake and remove the waiters which were not released yet, to make room.
+    fn evict_pending(&mut self) {
+        self.waiters.retain(|waiter| {
+            if waiter.released.is_some() {
+                true
+            } else {
+                waiter.waker.wake_by_ref();
+                false
+            }
+        });
+    }
+}
+
+/// Async event group like the one of FreeRTOS: a set of flag bits, and tasks wait until any or all of the bits of a mask are set.
+///
+/// Up to `N` waiters are stored together with their mask, and `set_bits` only wakes the ones which are released,
+/// like [`FilteredSignal`](crate::FilteredSignal). All waiters are checked against the bits before the bits of
+/// `clear_on_exit` waiters are cleared, so every waiter which shares a bit is released by the same `set_bits`.
+/// A released `clear_on_exit` wait which is dropped before it took its bits sets them again, like a dropped `acquire`
+/// of the [`Semaphore`](crate::Semaphore) gives back its permits.
+/// If more than `N` tasks register, the stored waiters are woken to make room, like `MultiWakerRegistration`.
+/// `M` protects the bits and the waiters, see [`SingleSlotSignal`](crate::SingleSlotSignal).
+pub struct EventGroup<const N: usize, M = ThreadModeRawMutex> {
+    inner: Mutex<M, RefCell<Inner<N>>>,
+}
+
+impl<const N: usize, M: RawMutex> EventGroup<N, M> {
+    pub const fn new() -> Self {
+        Self {
+            inner: Mutex::new(RefCell::new(Inner {
+                bits: 0,
+                waiters: Vec::new(),
+                next_id: 0,
+            })),
+        }
+    }
+
+    /// The current bits.
+    pub fn bits(&self) -> EventBits {
+        self.inner.lock(|inner| inner.borrow().bits)
+    }
+
+    /// Set `bits` and wake the waiters which are released by them. Returns the bits after the released waiters cleared theirs.
+    pub fn set_bits(&self, bits: EventBits) -> EventBits {
+        self.inner.lock(|inner| {
+            let mut inner = inner.borrow_mut();
+            inner.bits |= bits;
+            inner.release();
+            inner.bits
+        })
+    }
+
+    /// Clear `bits`. Returns the bits before they were cleared.
+    pub fn clear_bits(&self, bits: EventBits) -> EventBits {
+        self.inner.lock(|inner| {
+            let mut inner = inner.borrow_mut();
+            let before = inner.bits;
+            inner.bits &= !bits;
+            before
+        })
+    }
+
+    /// Wait until any bit of `mask` is set and return the bits at that time.
+    pub async fn wait_any(&self, mask: EventBits) -> EventBits {
+        self.wait(mask, false, false).await
+    }
+
+    /// Wait until all bits of `mask` are set and return the bits at that time.
+    ///
+    /// With `clear_on_exit` the bits of `mask` are cleared when the wait is released, e.g. to consume an event.
+    pub async fn wait_all(&self, mask: EventBits, clear_on_exit: bool) -> EventBits {
+        self.wait(mask, true, clear_on_exit).await
+    }
+
+    async fn wait(&self, mask: EventBits, all: bool, clear_on_exit: bool) -> EventBits {
+        // The waiter this wait stored, so a dropped wait can remove it again
+        let registered = Cell::new(None);
+        let _unregister = OnDrop::new(|| {
+            if let Some(id) = registered.get() {
+                self.inner.lock(|inner| inner.borrow_mut().unregister(id));
+            }
+        });
+
+        poll_fn(|cx| {
+            let task = WakerId::of(cx.waker());
+            trace!("Running waker of task: {}", task_name(task));
+
+            self.inner.lock(|inner| {
+                let mut inner = inner.borrow_mut();
+
+                // A waiter which was evicted to make room registers again
+                if let Some(i) = registered.get().and_then(|id| inner.position(id)) {
+                    let waiter = &mut inner.waiters[i];
+                    if let Some(bits) = waiter.released {
+                        inner.waiters.remove(i);
+                        registered.set(None);
+                        return Poll::Ready(bits);
+                    }
+                    if !waiter.waker.will_wake(cx.waker()) {
+                        waiter.waker = cx.waker().clone();
+                    }
+                    return Poll::Pending;
+                }
+
+                let bits = inner.bits;
+                if matches_mask(bits, mask, all) {
+                    if clear_on_exit {
+                        inner.bits &= !mask;
+                    }
+                    registered.set(None);
+                    return Poll::Ready(bits);
+                }
+
+                trace!("{}: Bits not set, registering waker", task_name(task));
+                if inner.waiters.is_full() {
+                    inner.evict_pending();
+                }
+                let id = inner.next_id;
+                let waiter = Waiter {
+                    id,
+                    waker: cx.waker().clone(),
+                    mask,
+                    all,
+                    clear_on_exit,
+                    released: None,
+                };
+                if inner.waiters.push(waiter).is_err() {
+                    // Only released waiters are stored, which leave on their next poll
+                    cx.waker().wake_by_ref();
+                    registered.set(None);
+                } else {
+                    inner.next_id = id.wrapping_add(1);
+                    registered.set(Some(id));
+                }
+                Poll::Pending
+            })
+        })
+        .await
+    }
+}
+
+impl<const N: usize, M: RawMutex> Default for EventGroup<N, M> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Task body like [`wait_for_signal`](crate::wait_for_signal), but the odd and even states are the bits
+/// [`ODD_BIT`] and [`EVEN_BIT`]. The task consumes its bit, so it is only woken when the bit is set again.
+pub async fn wait_for_event_bit<const N: usize, M: RawMutex>(
+    name: &'static str,
+    group: &EventGroup<N, M>,
+    odd: bool,
+) {
+    info!("Starting {} task", name);
+    let _name = register_current_task(name).await;
+
+    let (bit, kind) = if odd {
+        (ODD_BIT, "Odd")
+    } else {
+        (EVEN_BIT, "Even")
+    };
+
+    loop {
+        group.wait_all(bit, true).await;
+        info!("{}: {} bit set", name, kind);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use core::pin::pin;
+    use core::task::Context;
+
+    use super::*;
+    use crate::sim::{RunOrder, Simulator};
+    use crate::test_util::{CountingWaker, in_thread_mode};
+
+    const A: EventBits = 1 << 0;
+    const B: EventBits = 1 << 1;
+
+    #[test]
+    fn test_any_and_all() {
+        in_thread_mode(|| {
+            let group: EventGroup<2> = EventGroup::new();
+            let (any_counter, any_waker) = CountingWaker::new();
+            let (all_counter, all_waker) = CountingWaker::new();
+            let mut any_cx = Context::from_waker(&any_waker);
+            let mut all_cx = Context::from_waker(&all_waker);
+
+            let mut any = pin!(group.wait_any(A | B));
+            let mut all = pin!(group.wait_all(A | B, false));
+            assert_eq!(any.as_mut().poll(&mut any_cx), Poll::Pending);
+            assert_eq!(all.as_mut().poll(&mut all_cx), Poll::Pending);
+
+            // One bit releases `wait_any`, but not `wait_all`
+            assert_eq!(group.set_bits(B), B);
+            assert_eq!((any_counter.wakes(), all_counter.wakes()), (1, 0));
+            assert_eq!(any.poll(&mut any_cx), Poll::Ready(B));
+
+            assert_eq!(group.set_bits(A), A | B);
+            assert_eq!(all_counter.wakes(), 1);
+            assert_eq!(all.poll(&mut all_cx), Poll::Ready(A | B));
+
+            // Without `clear_on_exit` the bits stay set, and a new wait is ready right away
+            assert_eq!(group.bits(), A | B);
+            assert_eq!(
+                pin!(group.wait_all(A, true)).poll(&mut any_cx),
+                Poll::Ready(A | B)
+            );
+            assert_eq!(group.bits(), B);
+            assert_eq!(group.clear_bits(B), B);
+            assert_eq!(group.bits(), 0);
+        });
+    }
+
+    #[test]
+    fn test_shared_bits_wake_all() {
+        in_thread_mode(|| {
+            let group: EventGroup<3> = EventGroup::new();
+            let counters: [_; 3] = core::array::from_fn(|_| CountingWaker::new());
+            let mut one = pin!(group.wait_all(A, true));
+            let mut two = pin!(group.wait_all(A, true));
+            let mut three = pin!(group.wait_any(A | B));
+
+            let mut cx: [_; 3] = core::array::from_fn(|i| Context::from_waker(&counters[i].1));
+            assert_eq!(one.as_mut().poll(&mut cx[0]), Poll::Pending);
+            assert_eq!(two.as_mut().poll(&mut cx[1]), Poll::Pending);
+            assert_eq!(three.as_mut().poll(&mut cx[2]), Poll::Pending);
+
+            // Every waiter sees the bit before the first one clears it
+            assert_eq!(group.set_bits(A), 0);
+            for (counter, _) in &counters {
+                assert_eq!(counter.wakes(), 1);
+            }
+            assert_eq!(one.poll(&mut cx[0]), Poll::Ready(A));
+            assert_eq!(two.poll(&mut cx[1]), Poll::Ready(A));
+            assert_eq!(three.poll(&mut cx[2]), Poll::Ready(A));
+        });
+    }
+
+    /// Two waits of the same task, like both branches of a `select`, keep their own mask.
+    #[test]
+    fn test_waits_of_one_task_are_separate() {
+        in_thread_mode(|| {
+            let group: EventGroup<2> = EventGroup::new();
+            let (counter, waker) = CountingWaker::new();
+            let mut cx = Context::from_waker(&waker);
+
+            let mut any = pin!(group.wait_any(A));
+            let mut all = pin!(group.wait_all(B, true));
+            assert_eq!(any.as_mut().poll(&mut cx), Poll::Pending);
+            assert_eq!(all.as_mut().poll(&mut cx), Poll::Pending);
+
+            assert_eq!(group.set_bits(B), 0);
+            assert_eq!(counter.wakes(), 1);
+            assert_eq!(any.as_mut().poll(&mut cx), Poll::Pending);
+            assert_eq!(all.poll(&mut cx), Poll::Ready(B));
+
+            assert_eq!(group.set_bits(A), A);
+            assert_eq!(counter.wakes(), 2);
+            assert_eq!(any.poll(&mut cx), Poll::Ready(A));
+        });
+    }
+
+    #[test]
+    fn test_dropped_wait_unregisters() {
+        in_thread_mode(|| {
+            let group: EventGroup<1> = EventGroup::new();
+            let (counter, waker) = CountingWaker::new();
+            let mut cx = Context::from_waker(&waker);
+
+            {
+                let mut wait = pin!(group.wait_all(A, true));
+                assert_eq!(wait.as_mut().poll(&mut cx), Poll::Pending);
+            }
+
+            // The dropped wait neither consumes the bit nor is woken
+            assert_eq!(group.set_bits(A), A);
+            assert_eq!(counter.wakes(), 0);
+        });
+    }
+
+    /// A released `clear_on_exit` wait which is dropped before it took the bits, e.g. because the other branch of
+    /// a `select` won, gives them back to the other waiters.
+    #[test]
+    fn test_dropped_release_is_not_lost() {
+        in_thread_mode(|| {
+            let group: EventGroup<2> = EventGroup::new();
+            let (counter, waker) = CountingWaker::new();
+            let (other_counter, other_waker) = CountingWaker::new();
+            let mut cx = Context::from_waker(&waker);
+            let mut other_cx = Context::from_waker(&other_waker);
+
+            let mut other = pin!(group.wait_all(A | B, true));
+            assert_eq!(other.as_mut().poll(&mut other_cx), Poll::Pending);
+            {
+                let mut dropped = pin!(group.wait_all(A, true));
+                assert_eq!(dropped.as_mut().poll(&mut cx), Poll::Pending);
+
+                // Releases and clears `A` for the first wait, the second one still misses `B`
+                assert_eq!(group.set_bits(A), 0);
+                assert_eq!(counter.wakes(), 1);
+            }
+            assert_eq!(group.bits(), A);
+
+            assert_eq!(group.set_bits(B), 0);
+            assert_eq!(other_counter.wakes(), 1);
+            assert_eq!(other.poll(&mut other_cx), Poll::Ready(A | B));
+
+            // A waiter which registered after the release is released by the given back bits right away
+            let mut dropped = Box::pin(group.wait_all(A, true));
+            assert_eq!(dropped.as_mut().poll(&mut cx), Poll::Pending);
+            assert_eq!(group.set_bits(A), 0);
+            let mut other = pin!(group.wait_all(A, true));
+            assert_eq!(other.as_mut().poll(&mut other_cx), Poll::Pending);
+
+            drop(dropped);
+            assert_eq!(other_counter.wakes(), 2);
+            assert_eq!(other.poll(&mut other_cx), Poll::Ready(A));
+            assert_eq!(group.bits(), 0);
+        });
+    }
+
+    #[test]
+    fn test_odd_even_bits() {
+        const UPDATES: u32 = 8;
+
+        in_thread_mode(|| {
+            let group: EventGroup<2> = EventGroup::new();
+            let mut sim = Simulator::new(RunOrder::Embassy);
+            sim.spawn("TaskTwo", wait_for_event_bit("TaskTwo", &group, true));
+            sim.spawn("TaskOne", wait_for_event_bit("TaskOne", &group, false));
+
+            for counter in 1..=UPDATES {
+                sim.run_for(10);
+                group.set_bits(if counter % 2 == 1 { ODD_BIT } else { EVEN_BIT });
+            }
+            sim.run_for(10);
+
+            // Each task is only polled for its own bit, which it consumes
+            assert_eq!(sim.total_polls(), 2 + UPDATES as u64);
+            assert_eq!(group.bits(), 0);
+        });
+    }
+}
@@ -0,0 +1,394 @@
+use core::cell::{Cell, RefCell};
+use core::future::poll_fn;
+use core::task::{Poll, Waker};
+
+use embassy_sync::blocking_mutex::Mutex;
+use embassy_sync::blocking_mutex::raw::{RawMutex, ThreadModeRawMutex};
+use heapless::Vec;
+
+use crate::names::{register_current_task, task_name};
+use crate::signal::OnDrop;
+use crate::stats::ChurnProbe;
+use crate::{ChangeSignal, Closed, State, WakerId};
+
+/// Decides if a waiter wants to be woken for a state.
+pub type Predicate<T = State> = fn(&T) -> bool;
+
+/// Predicate of `wait_changed`, which is woken for every update.
+fn any_state<T>(_: &T) -> bool {
+    true
+}
+
+/// A stored waker and the states it wants to be woken for.
+struct Waiter<T> {
+    /// Identifies the wait, a task may wait several times at once, e.g. in a `select`
+    id: u32,
+    waker: Waker,
+    predicate: Predicate<T>,
+}
+
+struct Inner<const N: usize, T> {
+    state: RefCell<T>,
+    waiters: RefCell<Vec<Waiter<T>, N>>,
+    /// Wakes which were not issued because the predicate of the waiter did not match
+    polls_saved: Cell<u32>,
+    next_id: Cell<u32>,
+    closed: Cell<bool>,
+}
+
+impl<const N: usize, T> Inner<N, T> {
+    /// Wake and remove all waiters, e.g. to make room if the storage is full.
+    fn wake_all(&self, probe: &impl ChurnProbe) {
+        let mut waiters = self.waiters.borrow_mut();
+        while let Some(waiter) = waiters.pop() {
+            probe.woken(WakerId::of(&waiter.waker));
+            waiter.waker.wake();
+        }
+    }
+
+    /// Remove the waiter of a dropped wait without waking it.
+    fn unregister(&self, id: u32) {
+        self.waiters.borrow_mut().retain(|waiter| waiter.id != id);
+    }
+}
+
+/// Stores up to `N` wakers together with a predicate, and only wakes the waiters whose predicate matches the new state.
+///
+/// The predicate is checked in `set`, so a task which waits for odd states is not even polled for an even one.
+/// If more than `N` tasks register, all stored wakers are woken to make room, like `MultiWakerRegistration`.
+/// `M` protects the state and the waiters, see [`SingleSlotSignal`](crate::SingleSlotSignal).
+pub struct FilteredSignal<const N: usize, P = (), M = ThreadModeRawMutex, T = State> {
+    inner: Mutex<M, Inner<N, T>>,
+    probe: P,
+}
+
+impl<const N: usize, M: RawMutex> FilteredSignal<N, (), M> {
+    pub const fn new() -> Self {
+        Self::with_probe(())
+    }
+}
+
+impl<const N: usize, P: ChurnProbe, M: RawMutex> FilteredSignal<N, P, M> {
+    /// Create a signal which reports its churn to `probe`.
+    pub const fn with_probe(probe: P) -> Self {
+        Self::with_initial(State::NotReady, probe)
+    }
+}
+
+impl<const N: usize, P: ChurnProbe, M: RawMutex, T: Clone> FilteredSignal<N, P, M, T> {
+    /// Create a signal with a custom payload, starting at `initial`.
+    pub const fn with_initial(initial: T, probe: P) -> Self {
+        Self {
+            inner: Mutex::new(Inner {
+                state: RefCell::new(initial),
+                waiters: RefCell::new(Vec::new()),
+                polls_saved: Cell::new(0),
+                next_id: Cell::new(0),
+                closed: Cell::new(false),
+            }),
+            probe,
+        }
+    }
+
+    /// Number of wakes which were skipped because the waiter was not interested in the new state.
+    /// Filtering after the wake (like `wait_for_signal` does) would have polled the waiter for each of them.
+    pub fn polls_saved(&self) -> u32 {
+        self.inner.lock(|s| s.polls_saved.get())
+    }
+
+    /// Wait until the state differs from `current` and matches the predicate, and return that state.
+    ///
+    /// Like for `wait_changed`, `current` is the state the task saw last, so a matching update which was published
+    /// while the task did something else completes the wait right away instead of being lost.
+    pub async fn wait_until(&self, current: &T, predicate: Predicate<T>) -> Result<T, Closed>
+    where
+        T: PartialEq,
+    {
+        let mut first_poll = true;
+        // The waiter this wait stored, so a dropped wait can remove it again
+        let registered = Cell::new(None);
+        let _unregister = OnDrop::new(|| {
+            if let Some(id) = registered.get() {
+                self.inner.lock(|s| s.unregister(id));
+            }
+        });
+
+        poll_fn(|cx| {
+            let task = WakerId::of(cx.waker());
+            trace!("Running waker of task: {}", task_name(task));
+            let first = core::mem::replace(&mut first_poll, false);
+
+            self.inner.lock(|s| {
+                if s.closed.get() {
+                    registered.set(None);
+                    return Poll::Ready(Err(Closed));
+                }
+
+                let state = s.state.borrow();
+                if *state != *current && predicate(&state) {
+                    trace!("{}: Signal is ready", task_name(task));
+                    // The waiter is still stored if the task was polled for another reason, e.g. another wait of a `select`
+                    if let Some(id) = registered.take() {
+                        s.unregister(id);
+                    }
+                    self.probe.completed(task);
+                    return Poll::Ready(Ok(state.clone()));
+                }
+                drop(state);
+
+                trace!("{}: Signal not ready, registering waker", task_name(task));
+                if !first {
+                    self.probe.spurious_poll(task);
+                }
+                let id = self.register(s, registered.get(), task, cx.waker(), predicate);
+                registered.set(Some(id));
+                Poll::Pending
+            })
+        })
+        .await
+    }
+
+    /// Store the waker of a wait and return the id of its waiter. A wait which is still stored (`id`) keeps its waiter.
+    fn register(
+        &self,
+        s: &Inner<N, T>,
+        id: Option<u32>,
+        task: WakerId,
+        waker: &Waker,
+        predicate: Predicate<T>,
+    ) -> u32 {
+        self.probe.registered(task);
+
+        let mut waiters = s.waiters.borrow_mut();
+        if let Some(waiter) = waiters.iter_mut().find(|w| Some(w.id) == id) {
+            waiter.waker.clone_from(waker);
+            return waiter.id;
+        }
+
+        if waiters.is_full() {
+            for waiter in waiters.iter() {
+                self.probe.replaced(WakerId::of(&waiter.waker));
+            }
+            drop(waiters);
+            s.wake_all(&self.probe);
+            waiters = s.waiters.borrow_mut();
+        }
+
+        let id = s.next_id.get();
+        s.next_id.set(id.wrapping_add(1));
+        let _ = waiters.push(Waiter {
+            id,
+            waker: waker.clone(),
+            predicate,
+        });
+        id
+    }
+}
+
+impl<const N: usize, M: RawMutex, T: Clone + Default> Default for FilteredSignal<N, (), M, T> {
+    fn default() -> Self {
+        Self::with_initial(T::default(), ())
+    }
+}
+
+impl<const N: usize, P: ChurnProbe, M: RawMutex, T: Clone + PartialEq> ChangeSignal<T>
+    for FilteredSignal<N, P, M, T>
+{
+    fn with<R>(&self, f: impl FnOnce(&T) -> R) -> R {
+        self.inner.lock(|s| f(&s.state.borrow()))
+    }
+
+    fn set(&self, value: T) {
+        self.inner.lock(|s| {
+            *s.state.borrow_mut() = value;
+
+            let state = s.state.borrow();
+            s.waiters.borrow_mut().retain(|waiter| {
+                if (waiter.predicate)(&state) {
+                    self.probe.woken(WakerId::of(&waiter.waker));
+                    waiter.waker.wake_by_ref();
+                    false
+                } else {
+                    s.polls_saved.set(s.polls_saved.get().wrapping_add(1));
+                    true
+                }
+            });
+        });
+    }
+
+    /// Wakes all waiters, whatever their predicate is.
+    fn close(&self) {
+        self.inner.lock(|s| {
+            s.closed.set(true);
+            s.wake_all(&self.probe);
+        });
+    }
+
+    fn is_closed(&self) -> bool {
+        self.inner.lock(|s| s.closed.get())
+    }
+
+    async fn wait_changed(&self, current: &T) -> Result<T, Closed> {
+        self.wait_until(current, any_state).await
+    }
+}
+
+fn is_odd(state: &State) -> bool {
+    matches!(state, State::Ready(x) if x % 2 == 1)
+}
+
+fn is_even(state: &State) -> bool {
+    matches!(state, State::Ready(x) if x % 2 == 0)
+}
+
+/// Task body like [`wait_for_signal`](crate::wait_for_signal), but the task is only woken for the odd or even states.
+pub async fn wait_for_filtered_signal<const N: usize, P: ChurnProbe, M: RawMutex>(
+    name: &'static str,
+    signal: &FilteredSignal<N, P, M>,
+    odd: bool,
+) {
+    info!("Starting {} task", name);
+    let _name = register_current_task(name).await;
+
+    let predicate: Predicate = if odd { is_odd } else { is_even };
+    let kind = if odd { "Odd" } else { "Even" };
+    // The state at the start does not count, like the first state `wait_for_signal` checks is not an update
+    let mut current = signal.get();
+
+    loop {
+        let Ok(state) = signal.wait_until(&current, predicate).await else {
+            info!("{}: Signal closed", name);
+            return;
+        };
+
+        info!("{}: {} state: {:?}", name, kind, state);
+        current = state;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use core::pin::pin;
+    use core::task::Context;
+
+    use super::*;
+    use crate::sim::{RunOrder, Simulator};
+    use crate::test_util::{CountingWaker, in_thread_mode};
+    use crate::wait_for_signal;
+
+    const UPDATES: u32 = 8;
+
+    /// Run the odd/even demo tasks with the given task body and return the polls of both tasks.
+    fn run_demo<'a, F: Future<Output = ()> + 'a>(
+        signal: &'a FilteredSignal<2>,
+        task: impl Fn(&'static str, &'a FilteredSignal<2>, bool) -> F,
+    ) -> u64 {
+        let mut sim = Simulator::new(RunOrder::Embassy);
+        sim.spawn("TaskTwo", task("TaskTwo", signal, true));
+        sim.spawn("TaskOne", task("TaskOne", signal, false));
+
+        for counter in 1..=UPDATES {
+            sim.run_for(10);
+            signal.set(State::Ready(counter));
+        }
+        sim.run_for(10);
+
+        sim.total_polls()
+    }
+
+    #[test]
+    fn test_polls_saved() {
+        in_thread_mode(|| {
+            let filtering: FilteredSignal<2> = FilteredSignal::new();
+            let filtered_polls = run_demo(&filtering, wait_for_signal);
+            let predicate: FilteredSignal<2> = FilteredSignal::new();
+            let predicate_polls = run_demo(&predicate, wait_for_filtered_signal);
+
+            // Both tasks are polled for every update vs. only for the ones they wait for
+            assert_eq!(filtered_polls, 2 + 2 * UPDATES as u64);
+            assert_eq!(predicate_polls, 2 + UPDATES as u64);
+            assert_eq!(filtering.polls_saved(), 0);
+            assert_eq!(
+                predicate.polls_saved() as u64,
+                filtered_polls - predicate_polls
+            );
+        });
+    }
+
+    #[test]
+    fn test_only_new_updates() {
+        in_thread_mode(|| {
+            let signal: FilteredSignal<1> = FilteredSignal::new();
+            signal.set(State::Ready(1));
+
+            let result = core::cell::Cell::new(None);
+            let mut sim = Simulator::new(RunOrder::Embassy);
+            sim.spawn("TaskTwo", async {
+                result.set(Some(signal.wait_until(&State::Ready(1), is_odd).await));
+            });
+
+            // Ready(1) was already seen, and Ready(2) is not odd
+            sim.run_for(10);
+            signal.set(State::Ready(2));
+            sim.run_for(10);
+            assert_eq!(result.get(), None);
+
+            signal.set(State::Ready(3));
+            sim.run_for(10);
+            assert_eq!(result.get(), Some(Ok(State::Ready(3))));
+        });
+    }
+
+    /// Two waits of the same task, like both branches of a `select`, keep their own predicate.
+    #[test]
+    fn test_waits_of_one_task_are_separate() {
+        in_thread_mode(|| {
+            let signal: FilteredSignal<2> = FilteredSignal::new();
+            let (counter, waker) = CountingWaker::new();
+            let mut cx = Context::from_waker(&waker);
+
+            let mut odd = pin!(signal.wait_until(&State::NotReady, is_odd));
+            let mut even = pin!(signal.wait_until(&State::NotReady, is_even));
+            assert!(odd.as_mut().poll(&mut cx).is_pending());
+            assert!(even.as_mut().poll(&mut cx).is_pending());
+
+            // The even wait did not replace the predicate of the odd one
+            signal.set(State::Ready(1));
+            assert_eq!(counter.wakes(), 1);
+            assert_eq!(odd.poll(&mut cx), Poll::Ready(Ok(State::Ready(1))));
+            assert!(even.as_mut().poll(&mut cx).is_pending());
+
+            {
+                let mut odd = pin!(signal.wait_until(&State::Ready(1), is_odd));
+                assert!(odd.as_mut().poll(&mut cx).is_pending());
+            }
+
+            // Dropping the odd wait left the even one stored
+            signal.set(State::Ready(2));
+            assert_eq!(counter.wakes(), 2);
+            assert_eq!(even.poll(&mut cx), Poll::Ready(Ok(State::Ready(2))));
+        });
+    }
+
+    #[test]
+    fn test_update_between_waits_is_kept() {
+        in_thread_mode(|| {
+            let signal: FilteredSignal<1> = FilteredSignal::new();
+            let (_, waker) = CountingWaker::new();
+            let mut cx = Context::from_waker(&waker);
+
+            let mut wait = pin!(signal.wait_until(&State::NotReady, is_odd));
+            assert!(wait.as_mut().poll(&mut cx).is_pending());
+            signal.set(State::Ready(1));
+            let Poll::Ready(Ok(current)) = wait.poll(&mut cx) else {
+                panic!("first wait not ready");
+            };
+
+            // The task does some work before it waits again, meanwhile a matching update is published
+            signal.set(State::Ready(2));
+            signal.set(State::Ready(3));
+            let wait = pin!(signal.wait_until(&current, is_odd));
+            assert_eq!(wait.poll(&mut cx), Poll::Ready(Ok(State::Ready(3))));
+        });
+    }
+}
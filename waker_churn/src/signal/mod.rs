@@ -0,0 +1,713 @@
+use core::future::Future;
+
+use embassy_futures::select::{Either, Either3, Either4, select, select_array, select3, select4};
+use embassy_time::{Duration, with_timeout};
+
+use crate::State;
+use crate::names::register_current_task;
+
+pub(crate) mod barrier;
+pub(crate) mod bounded;
+pub(crate) mod broadcast;
+pub(crate) mod coalescing;
+pub(crate) mod condvar;
+pub(crate) mod event_group;
+pub(crate) mod filtered;
+pub(crate) mod multi_waker_registration;
+pub(crate) mod priority;
+pub(crate) mod semaphore;
+pub(crate) mod single_slot;
+pub(crate) mod versioned;
+pub(crate) mod wait_queue;
+
+/// Error of a wait on a signal which was closed by [`ChangeSignal::close`].
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Closed;
+
+/// Error of [`ChangeSignal::wait_changed_timeout`].
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WaitError {
+    /// The state did not change within the timeout.
+    Timeout,
+    /// The signal was closed.
+    Closed,
+}
+
+impl From<Closed> for WaitError {
+    fn from(_: Closed) -> Self {
+        Self::Closed
+    }
+}
+
+/// A signal which holds the latest value and wakes waiting tasks when it is changed.
+///
+/// This is the common interface of all waker strategies, so the demos (and tests) can swap them freely.
+/// The demos publish a [`State`], which is the default payload. Any `T: Clone + PartialEq` works as well, e.g. the
+/// real application state. The value is compared and read by reference, so only `get` and `wait_changed` clone it.
+pub trait ChangeSignal<T: Clone + PartialEq = State> {
+    /// Borrow the current value.
+    fn with<R>(&self, f: impl FnOnce(&T) -> R) -> R;
+
+    /// Get a copy of the current value.
+    fn get(&self) -> T {
+        self.with(T::clone)
+    }
+
+    /// Publish a new value and wake the registered waiters.
+    fn set(&self, value: T);
+
+    /// Close the signal, e.g. because the producer shuts down.
+    /// All waiters are woken, and current as well as future waits return [`Closed`].
+    fn close(&self);
+
+    /// True if the signal was closed.
+    fn is_closed(&self) -> bool;
+
+    /// Wait until the value differs from `current` and return the new value.
+    fn wait_changed(&self, current: &T) -> impl Future<Output = Result<T, Closed>>;
+
+    /// Like [`wait_changed`](ChangeSignal::wait_changed), but gives up after `timeout`.
+    ///
+    /// A change which is already visible when the timeout expires wins. On timeout the wait is dropped,
+    /// which removes its waker from the signal, so no stale registration is left behind.
+    fn wait_changed_timeout(
+        &self,
+        current: &T,
+        timeout: Duration,
+    ) -> impl Future<Output = Result<T, WaitError>> {
+        async move {
+            match with_timeout(timeout, self.wait_changed(current)).await {
+                Ok(result) => Ok(result?),
+                Err(_) => Err(WaitError::Timeout),
+            }
+        }
+    }
+}
+
+/// Signals which [`wait_any`] waits on together.
+///
+/// An array of signals of the same type returns the index of the signal which changed together with its value.
+/// A tuple of two to four signals, each with its own strategy and payload `T`, returns the value of the signal which changed
+/// as an `Either`, `Either3` or `Either4` of `embassy_futures::select`, e.g. `Either3::Second(Ok(config))`.
+pub trait SignalSet<T> {
+    /// What the wait on the signals returns.
+    type Output;
+
+    /// Read the current values and wait until one of the signals changes, see [`wait_any`].
+    fn wait_any(self) -> impl Future<Output = Self::Output>;
+}
+
+impl<const N: usize, T: Clone + PartialEq, S: ChangeSignal<T>> SignalSet<T> for [&S; N] {
+    type Output = (usize, Result<T, Closed>);
+
+    fn wait_any(self) -> impl Future<Output = Self::Output> {
+        let currents = self.map(|signal| signal.get());
+
+        async move {
+            let waits: [_; N] = core::array::from_fn(|i| self[i].wait_changed(&currents[i]));
+            let (result, index) = select_array(waits).await;
+            (index, result)
+        }
+    }
+}
+
+macro_rules! signal_tuple {
+    ($either:ident, $select:ident, $(($s:ident, $t:ident, $i:tt)),+) => {
+        impl<$($t: Clone + PartialEq, $s: ChangeSignal<$t>),+> SignalSet<($($t,)+)> for ($(&$s,)+) {
+            type Output = $either<$(Result<$t, Closed>),+>;
+
+            fn wait_any(self) -> impl Future<Output = Self::Output> {
+                let currents = ($(self.$i.get(),)+);
+
+                async move { $select($(self.$i.wait_changed(&currents.$i)),+).await }
+            }
+        }
+    };
+}
+
+signal_tuple!(Either, select, (S1, T1, 0), (S2, T2, 1));
+signal_tuple!(Either3, select3, (S1, T1, 0), (S2, T2, 1), (S3, T3, 2));
+signal_tuple!(
+    Either4,
+    select4,
+    (S1, T1, 0),
+    (S2, T2, 1),
+    (S3, T3, 2),
+    (S4, T4, 3)
+);
+
+/// Wait until one of the signals changes and return which one together with its new value, see [`SignalSet`].
+///
+/// The values to compare with are read when the wait is created. The task waits on every signal at once,
+/// and when one of them changes (or the whole wait is dropped) the waits on the others are dropped, which removes their wakers.
+/// A closed signal completes the wait with [`Closed`], so e.g. a shutdown request can be a signal which is closed.
+pub fn wait_any<T, S: SignalSet<T>>(signals: S) -> impl Future<Output = S::Output> {
+    signals.wait_any()
+}
+
+/// A signal which hands each update to exactly one waiter instead of broadcasting it.
+///
+/// The waiters are served in FIFO order, so a pool of workers can share the jobs of a producer.
+pub trait HandOffSignal<T: Clone + PartialEq = State>: ChangeSignal<T> {
+    /// Publish a new value and wake only the task which waits the longest.
+    ///
+    /// If no task waits, the next call of [`wait_handed`](HandOffSignal::wait_handed) takes the update.
+    /// Updates are not queued: an update which was not taken yet is replaced, like `set` replaces the value,
+    /// and returned, so the producer can see that no worker got it (e.g. in a burst) and retry or count it.
+    fn notify_one(&self, value: T) -> Option<T>;
+
+    /// Wait until an update is handed to this task and return it.
+    fn wait_handed(&self) -> impl Future<Output = Result<T, Closed>>;
+}
+
+/// Task body of a worker in a pool: take the updates of [`HandOffSignal::notify_one`] one by one, until the signal is closed.
+pub async fn work_on_signal<S: HandOffSignal>(name: &'static str, signal: &S) {
+    info!("Starting {} task", name);
+    let _name = register_current_task(name).await;
+
+    while let Ok(state) = signal.wait_handed().await {
+        info!("{}: Took job: {:?}", name, state);
+    }
+
+    info!("{}: Signal closed", name);
+}
+
+/// Runs a closure when dropped.
+///
+/// The poll based strategies use it to remove the waker of a wait which is dropped before it completed, e.g. on a timeout.
+pub(crate) struct OnDrop<F: FnOnce()>(Option<F>);
+
+impl<F: FnOnce()> OnDrop<F> {
+    pub(crate) fn new(f: F) -> Self {
+        Self(Some(f))
+    }
+}
+
+impl<F: FnOnce()> Drop for OnDrop<F> {
+    fn drop(&mut self) {
+        if let Some(f) = self.0.take() {
+            f();
+        }
+    }
+}
+
+/// Task body of the demos: log the odd or even states and wait for the next change, until the signal is closed.
+/// The task registers `name` in the [`names`](crate::names) registry, so the trace lines of the strategies can print it.
+pub async fn wait_for_signal<S: ChangeSignal>(name: &'static str, signal: &S, odd: bool) {
+    info!("Starting {} task", name);
+    let _name = register_current_task(name).await;
+
+    loop {
+        let current_state = signal.get();
+
+        match (odd, current_state) {
+            (true, State::Ready(x)) if x % 2 == 1 => {
+                info!("{}: Odd state: {:?}", name, current_state);
+            }
+            (false, State::Ready(x)) if x % 2 == 0 => {
+                info!("{}: Even state: {:?}", name, current_state);
+            }
+            _ => {}
+        }
+
+        if signal.wait_changed(&current_state).await.is_err() {
+            info!("{}: Signal closed", name);
+            return;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use core::cell::RefCell;
+    use core::pin::pin;
+    use core::task::{Context, Poll};
+
+    use embassy_sync::blocking_mutex::raw::{CriticalSectionRawMutex, ThreadModeRawMutex};
+
+    use super::*;
+    use crate::sim::{RunOrder, Simulator};
+    use crate::test_util::{CountingWaker, in_thread_mode, with_mock_time};
+    use crate::{
+        AtomicWakerSignal, FilteredSignal, MultiWakerRegistrationSignal, NaiveSignal,
+        PrioritySignal, VersionedSignal, WaitQueueSignal, WakerRegistrationSignal,
+    };
+
+    fn wakes_single_waiter<S: ChangeSignal + Default>() {
+        let signal = S::default();
+        let (counter, waker) = CountingWaker::new();
+        let mut cx = Context::from_waker(&waker);
+
+        let mut wait = pin!(signal.wait_changed(&State::NotReady));
+        assert_eq!(wait.as_mut().poll(&mut cx), Poll::Pending);
+        assert_eq!(counter.wakes(), 0);
+
+        signal.set(State::Ready(1));
+        assert_eq!(counter.wakes(), 1);
+        assert_eq!(
+            wait.as_mut().poll(&mut cx),
+            Poll::Ready(Ok(State::Ready(1)))
+        );
+    }
+
+    fn ready_if_already_changed<S: ChangeSignal + Default>() {
+        let signal = S::default();
+        signal.set(State::Ready(2));
+
+        let (_, waker) = CountingWaker::new();
+        let mut cx = Context::from_waker(&waker);
+
+        let wait = pin!(signal.wait_changed(&State::NotReady));
+        assert_eq!(wait.poll(&mut cx), Poll::Ready(Ok(State::Ready(2))));
+    }
+
+    fn close_wakes_waiter<S: ChangeSignal + Default>() {
+        let signal = S::default();
+        let (counter, waker) = CountingWaker::new();
+        let mut cx = Context::from_waker(&waker);
+
+        let mut wait = pin!(signal.wait_changed(&State::NotReady));
+        assert_eq!(wait.as_mut().poll(&mut cx), Poll::Pending);
+
+        signal.close();
+        assert!(signal.is_closed());
+        assert_eq!(counter.wakes(), 1);
+        assert_eq!(wait.as_mut().poll(&mut cx), Poll::Ready(Err(Closed)));
+
+        // Also if the state changed in the meantime
+        signal.set(State::Ready(1));
+        let wait = pin!(signal.wait_changed(&State::NotReady));
+        assert_eq!(wait.poll(&mut cx), Poll::Ready(Err(Closed)));
+    }
+
+    const TIMEOUT: Duration = Duration::from_millis(10);
+
+    fn timeout_removes_waker<S: ChangeSignal + Default>() {
+        with_mock_time(|time| {
+            let signal = S::default();
+            let (counter, waker) = CountingWaker::new();
+            let mut cx = Context::from_waker(&waker);
+
+            {
+                let mut wait = pin!(signal.wait_changed_timeout(&State::NotReady, TIMEOUT));
+                assert_eq!(wait.as_mut().poll(&mut cx), Poll::Pending);
+
+                time.advance(TIMEOUT);
+                assert_eq!(counter.wakes(), 1);
+                assert_eq!(wait.poll(&mut cx), Poll::Ready(Err(WaitError::Timeout)));
+            }
+
+            // The timed out wait must not be woken by a stale registration
+            signal.set(State::Ready(1));
+            assert_eq!(counter.wakes(), 1);
+        });
+    }
+
+    fn change_wins_over_timeout<S: ChangeSignal + Default>() {
+        with_mock_time(|time| {
+            let signal = S::default();
+            let (_, waker) = CountingWaker::new();
+            let mut cx = Context::from_waker(&waker);
+
+            let mut wait = pin!(signal.wait_changed_timeout(&State::NotReady, TIMEOUT));
+            assert_eq!(wait.as_mut().poll(&mut cx), Poll::Pending);
+
+            // Both happen before the task is polled again
+            signal.set(State::Ready(1));
+            time.advance(TIMEOUT);
+            assert_eq!(wait.poll(&mut cx), Poll::Ready(Ok(State::Ready(1))));
+        });
+    }
+
+    fn wait_any_deregisters<S: ChangeSignal + Default>() {
+        let signals = [S::default(), S::default(), S::default()];
+        let [state, config, shutdown] = &signals;
+        let (counter, waker) = CountingWaker::new();
+        let mut cx = Context::from_waker(&waker);
+
+        {
+            let mut wait = pin!(wait_any([state, config, shutdown]));
+            assert_eq!(wait.as_mut().poll(&mut cx), Poll::Pending);
+
+            config.set(State::Ready(1));
+            assert_eq!(counter.wakes(), 1);
+            assert_eq!(wait.poll(&mut cx), Poll::Ready((1, Ok(State::Ready(1)))));
+        }
+
+        // The waits on the other signals were dropped together with their wakers
+        state.set(State::Ready(2));
+        shutdown.close();
+        assert_eq!(counter.wakes(), 1);
+
+        {
+            let mut wait = pin!(wait_any([state, config]));
+            assert_eq!(wait.as_mut().poll(&mut cx), Poll::Pending);
+        }
+
+        // Also if the wait is cancelled
+        state.set(State::Ready(3));
+        config.set(State::Ready(3));
+        assert_eq!(counter.wakes(), 1);
+
+        let wait = pin!(wait_any([state, shutdown]));
+        assert_eq!(wait.poll(&mut cx), Poll::Ready((1, Err(Closed))));
+    }
+
+    /// A state, a config of another payload and strategy, and a shutdown request, like in the README.
+    #[test]
+    fn test_wait_any_mixed_signals() {
+        in_thread_mode(|| {
+            let state: MultiWakerRegistrationSignal<1> = MultiWakerRegistrationSignal::new();
+            let config: WaitQueueSignal<(), ThreadModeRawMutex, u32> =
+                WaitQueueSignal::with_initial(100, ());
+            let shutdown: VersionedSignal = VersionedSignal::new();
+            let (counter, waker) = CountingWaker::new();
+            let mut cx = Context::from_waker(&waker);
+
+            {
+                let mut wait = pin!(wait_any((&state, &config, &shutdown)));
+                assert!(wait.as_mut().poll(&mut cx).is_pending());
+
+                config.set(250);
+                assert_eq!(counter.wakes(), 1);
+                assert!(matches!(
+                    wait.poll(&mut cx),
+                    Poll::Ready(Either3::Second(Ok(250)))
+                ));
+            }
+
+            // The waits on the other signals were dropped together with their wakers
+            state.set(State::Ready(1));
+            assert_eq!(counter.wakes(), 1);
+
+            let mut wait = pin!(wait_any((&config, &shutdown)));
+            assert!(wait.as_mut().poll(&mut cx).is_pending());
+            shutdown.close();
+            assert!(matches!(
+                wait.poll(&mut cx),
+                Poll::Ready(Either::Second(Err(Closed)))
+            ));
+        });
+    }
+
+    /// Two waits of one task, like the branches of a `select`: dropping one of them, e.g. on its timeout,
+    /// must not remove the waker of the other one, whichever of them registered last.
+    fn dropped_wait_keeps_other_wait<S: ChangeSignal + Default>() {
+        for drop_last in [false, true] {
+            let signal = S::default();
+            let (counter, waker) = CountingWaker::new();
+            let mut cx = Context::from_waker(&waker);
+
+            let mut first = Box::pin(signal.wait_changed(&State::NotReady));
+            let mut second = Box::pin(signal.wait_changed(&State::NotReady));
+            assert_eq!(first.as_mut().poll(&mut cx), Poll::Pending);
+            assert_eq!(second.as_mut().poll(&mut cx), Poll::Pending);
+            let (mut live, dropped) = if drop_last {
+                (first, second)
+            } else {
+                (second, first)
+            };
+            drop(dropped);
+
+            signal.set(State::Ready(1));
+            assert_eq!(counter.wakes(), 1, "drop_last: {drop_last}");
+            assert_eq!(
+                live.as_mut().poll(&mut cx),
+                Poll::Ready(Ok(State::Ready(1)))
+            );
+        }
+    }
+
+    fn check<S: ChangeSignal + Default>() {
+        in_thread_mode(|| {
+            dropped_wait_keeps_other_wait::<S>();
+            wait_any_deregisters::<S>();
+            wakes_single_waiter::<S>();
+            ready_if_already_changed::<S>();
+            close_wakes_waiter::<S>();
+            timeout_removes_waker::<S>();
+            change_wins_over_timeout::<S>();
+        });
+    }
+
+    /// A timed out wait only removes its own waker, the other waiters are still woken.
+    fn timeout_keeps_other_waiters<S: ChangeSignal + Default>() {
+        with_mock_time(|time| {
+            let signal = S::default();
+            let (timed_out, timed_out_waker) = CountingWaker::new();
+            let (other, other_waker) = CountingWaker::new();
+
+            let mut other_wait = pin!(signal.wait_changed(&State::NotReady));
+            let mut other_cx = Context::from_waker(&other_waker);
+            assert_eq!(other_wait.as_mut().poll(&mut other_cx), Poll::Pending);
+
+            {
+                let mut cx = Context::from_waker(&timed_out_waker);
+                let mut wait = pin!(signal.wait_changed_timeout(&State::NotReady, TIMEOUT));
+                assert_eq!(wait.as_mut().poll(&mut cx), Poll::Pending);
+
+                time.advance(TIMEOUT);
+                assert_eq!(wait.poll(&mut cx), Poll::Ready(Err(WaitError::Timeout)));
+            }
+
+            signal.set(State::Ready(1));
+            assert_eq!(timed_out.wakes(), 1);
+            assert_eq!(other.wakes(), 1);
+            assert_eq!(
+                other_wait.poll(&mut other_cx),
+                Poll::Ready(Ok(State::Ready(1)))
+            );
+        });
+    }
+
+    #[test]
+    fn test_timeout_keeps_other_waiters() {
+        in_thread_mode(|| {
+            timeout_keeps_other_waiters::<MultiWakerRegistrationSignal<2>>();
+            timeout_keeps_other_waiters::<WaitQueueSignal>();
+            timeout_keeps_other_waiters::<VersionedSignal>();
+            timeout_keeps_other_waiters::<FilteredSignal<2>>();
+            timeout_keeps_other_waiters::<PrioritySignal<2>>();
+        });
+    }
+
+    /// The "interrupt" is another thread, which takes the std implementation of the critical section.
+    /// No `in_thread_mode` here: with `CriticalSectionRawMutex` neither side needs to run on the `main` thread.
+    fn set_from_interrupt<S: ChangeSignal + Default + Sync>() {
+        let signal = S::default();
+        let (counter, waker) = CountingWaker::new();
+        let mut cx = Context::from_waker(&waker);
+
+        let mut wait = pin!(signal.wait_changed(&State::NotReady));
+        assert_eq!(wait.as_mut().poll(&mut cx), Poll::Pending);
+
+        std::thread::scope(|scope| {
+            scope.spawn(|| signal.set(State::Ready(1)));
+        });
+
+        assert_eq!(counter.wakes(), 1);
+        assert_eq!(
+            wait.as_mut().poll(&mut cx),
+            Poll::Ready(Ok(State::Ready(1)))
+        );
+    }
+
+    #[test]
+    fn test_critical_section() {
+        type M = CriticalSectionRawMutex;
+
+        set_from_interrupt::<NaiveSignal<(), M>>();
+        set_from_interrupt::<AtomicWakerSignal<(), M>>();
+        set_from_interrupt::<WakerRegistrationSignal<(), M>>();
+        set_from_interrupt::<MultiWakerRegistrationSignal<2, (), M>>();
+        set_from_interrupt::<WaitQueueSignal<(), M>>();
+        set_from_interrupt::<VersionedSignal<M>>();
+        set_from_interrupt::<FilteredSignal<2, (), M>>();
+        set_from_interrupt::<PrioritySignal<2, (), M>>();
+    }
+
+    #[test]
+    fn test_close_ends_tasks() {
+        in_thread_mode(|| {
+            let signal: WaitQueueSignal = WaitQueueSignal::new();
+            let mut sim = Simulator::new(RunOrder::Embassy);
+            sim.spawn("TaskTwo", wait_for_signal("TaskTwo", &signal, true));
+            sim.spawn("TaskOne", wait_for_signal("TaskOne", &signal, false));
+
+            sim.run_for(10);
+            signal.set(State::Ready(1));
+            sim.run_for(10);
+            assert!(sim.tasks().iter().all(|task| !task.completed));
+
+            signal.close();
+            sim.run_for(10);
+            assert!(sim.tasks().iter().all(|task| task.completed));
+        });
+    }
+
+    const JOBS: u32 = 9;
+
+    /// Hand `JOBS` updates to a pool of three workers and return the names of the workers in the order they took them,
+    /// together with the total number of polls.
+    fn take_turns<S: HandOffSignal + Default>() -> (Vec<&'static str>, u64) {
+        let signal = S::default();
+        let turns = RefCell::new(Vec::new());
+
+        let worker = |name: &'static str| {
+            let (signal, turns) = (&signal, &turns);
+            async move {
+                while signal.wait_handed().await.is_ok() {
+                    turns.borrow_mut().push(name);
+                }
+            }
+        };
+
+        let mut sim = Simulator::new(RunOrder::Embassy);
+        sim.spawn("TaskTwo", worker("TaskTwo"));
+        sim.spawn("TaskOne", worker("TaskOne"));
+        sim.spawn("TaskThree", worker("TaskThree"));
+
+        for counter in 1..=JOBS {
+            sim.run_for(10);
+            assert_eq!(signal.notify_one(State::Ready(counter)), None);
+        }
+        sim.run_for(10);
+
+        let polls = sim.total_polls();
+        drop(sim);
+        (turns.into_inner(), polls)
+    }
+
+    fn check_fairness<S: HandOffSignal + Default>() {
+        in_thread_mode(|| {
+            let (turns, polls) = take_turns::<S>();
+
+            // Every job is handed to exactly one worker, and the workers take turns
+            assert_eq!(turns.len(), JOBS as usize);
+            for name in ["TaskOne", "TaskTwo", "TaskThree"] {
+                let taken = turns.iter().filter(|&&taken| taken == name).count();
+                assert_eq!(taken, JOBS as usize / 3, "{name}: {turns:?}");
+            }
+            for (i, name) in turns.iter().enumerate() {
+                assert_eq!(*name, turns[i % 3], "{turns:?}");
+            }
+
+            // Only the worker which takes the job is polled
+            assert_eq!(polls, 3 + JOBS as u64);
+        });
+    }
+
+    /// Two updates in a row, before the woken worker ran: the first one is returned instead of silently lost.
+    fn burst_returns_replaced<S: HandOffSignal + Default>() {
+        in_thread_mode(|| {
+            let signal = S::default();
+            let (counter, waker) = CountingWaker::new();
+            let mut cx = Context::from_waker(&waker);
+
+            let mut wait = pin!(signal.wait_handed());
+            assert_eq!(wait.as_mut().poll(&mut cx), Poll::Pending);
+
+            assert_eq!(signal.notify_one(State::Ready(1)), None);
+            assert_eq!(signal.notify_one(State::Ready(2)), Some(State::Ready(1)));
+            assert!(counter.wakes() >= 1);
+            assert_eq!(wait.poll(&mut cx), Poll::Ready(Ok(State::Ready(2))));
+
+            // Without any waiter the update is kept for the next one
+            assert_eq!(signal.notify_one(State::Ready(3)), None);
+            let wait = pin!(signal.wait_handed());
+            assert_eq!(wait.poll(&mut cx), Poll::Ready(Ok(State::Ready(3))));
+        });
+    }
+
+    #[test]
+    fn test_fairness_multi_waker_registration() {
+        check_fairness::<MultiWakerRegistrationSignal<3>>();
+        burst_returns_replaced::<MultiWakerRegistrationSignal<3>>();
+    }
+
+    #[test]
+    fn test_fairness_wait_queue() {
+        check_fairness::<WaitQueueSignal>();
+        burst_returns_replaced::<WaitQueueSignal>();
+    }
+
+    #[test]
+    fn test_fairness_priority() {
+        check_fairness::<PrioritySignal<3>>();
+        burst_returns_replaced::<PrioritySignal<3>>();
+    }
+
+    /// A payload which is not `Copy`, like a frame of sensor samples.
+    #[derive(Debug, Clone, PartialEq, Default)]
+    struct Frame {
+        seq: u32,
+        samples: heapless::Vec<i16, 8>,
+    }
+
+    fn frame(seq: u32) -> Frame {
+        Frame {
+            seq,
+            samples: (0..8).map(|i| seq as i16 * i).collect(),
+        }
+    }
+
+    fn borrowed_payload<S: ChangeSignal<Frame> + Default>() {
+        let signal = S::default();
+        let (counter, waker) = CountingWaker::new();
+        let mut cx = Context::from_waker(&waker);
+
+        signal.set(frame(1));
+        let current = signal.get();
+        let mut wait = pin!(signal.wait_changed(&current));
+        assert_eq!(wait.as_mut().poll(&mut cx), Poll::Pending);
+
+        // The same frame again is not a change
+        signal.set(frame(1));
+        if counter.wakes() > 0 {
+            assert_eq!(wait.as_mut().poll(&mut cx), Poll::Pending);
+        }
+
+        signal.set(frame(2));
+        assert_eq!(wait.poll(&mut cx), Poll::Ready(Ok(frame(2))));
+
+        // Read a sample without copying the frame
+        assert_eq!(signal.with(|frame| frame.samples[3]), 6);
+        assert_eq!(signal.with(|frame| frame.seq), 2);
+    }
+
+    #[test]
+    fn test_borrowed_payload() {
+        in_thread_mode(|| {
+            borrowed_payload::<NaiveSignal<(), ThreadModeRawMutex, Frame>>();
+            borrowed_payload::<AtomicWakerSignal<(), ThreadModeRawMutex, Frame>>();
+            borrowed_payload::<WakerRegistrationSignal<(), ThreadModeRawMutex, Frame>>();
+            borrowed_payload::<MultiWakerRegistrationSignal<2, (), ThreadModeRawMutex, Frame>>();
+            borrowed_payload::<WaitQueueSignal<(), ThreadModeRawMutex, Frame>>();
+            borrowed_payload::<VersionedSignal<ThreadModeRawMutex, Frame>>();
+            borrowed_payload::<FilteredSignal<2, (), ThreadModeRawMutex, Frame>>();
+            borrowed_payload::<PrioritySignal<2, (), ThreadModeRawMutex, Frame>>();
+        });
+    }
+
+    #[test]
+    fn test_naive() {
+        check::<NaiveSignal>();
+    }
+
+    #[test]
+    fn test_atomic_waker() {
+        check::<AtomicWakerSignal>();
+    }
+
+    #[test]
+    fn test_waker_registration() {
+        check::<WakerRegistrationSignal>();
+    }
+
+    #[test]
+    fn test_multi_waker_registration() {
+        check::<MultiWakerRegistrationSignal<2>>();
+    }
+
+    #[test]
+    fn test_wait_queue() {
+        check::<WaitQueueSignal>();
+    }
+
+    #[test]
+    fn test_versioned() {
+        check::<VersionedSignal>();
+    }
+
+    #[test]
+    fn test_filtered() {
+        check::<FilteredSignal<2>>();
+    }
+
+    #[test]
+    fn test_priority() {
+        check::<PrioritySignal<2>>();
+    }
+}
@@ -0,0 +1,288 @@
+use core::cell::{Cell, RefCell};
+use core::future::poll_fn;
+use core::task::{Poll, Waker};
+
+use embassy_sync::blocking_mutex::Mutex;
+use embassy_sync::blocking_mutex::raw::{RawMutex, ThreadModeRawMutex};
+use embassy_sync::waitqueue::MultiWakerRegistration;
+use heapless::Vec;
+
+use crate::names::task_name;
+use crate::signal::OnDrop;
+use crate::stats::ChurnProbe;
+use crate::{ChangeSignal, Closed, HandOffSignal, State, WakerId};
+
+/// A copy of a stored waker and the wait it belongs to.
+struct Waiter {
+    /// Identifies the wait, a task may wait several times at once, e.g. in a `select`
+    id: u32,
+    waker: Waker,
+}
+
+struct Inner<const N: usize, T> {
+    state: RefCell<T>,
+    waker_registration: RefCell<MultiWakerRegistration<N>>,
+    /// Copies of the wakers stored in `waker_registration`, one per wait, in the order they were registered.
+    /// `MultiWakerRegistration` can't remove a single waker, so it is rebuilt from these when a wait is dropped.
+    registered: RefCell<Vec<Waiter, N>>,
+    next_id: Cell<u32>,
+    /// Update of `notify_one` which no waiter took yet
+    handed: Cell<Option<T>>,
+    closed: Cell<bool>,
+}
+
+impl<const N: usize, T> Inner<N, T> {
+    /// Mirrors `MultiWakerRegistration::wake`, which wakes and removes all stored wakers.
+    fn wake_registered(&self, probe: &impl ChurnProbe) {
+        let mut registered = self.registered.borrow_mut();
+        for waiter in registered.iter() {
+            probe.woken(WakerId::of(&waiter.waker));
+        }
+        registered.clear();
+    }
+
+    /// Remove the waker of a dropped wait without waking the others.
+    /// Another wait of the same task keeps the waker in `waker_registration`.
+    fn unregister(&self, id: u32) {
+        let mut registered = self.registered.borrow_mut();
+        let Some(i) = registered.iter().position(|waiter| waiter.id == id) else {
+            return;
+        };
+
+        registered.remove(i);
+        drop(registered);
+        self.rebuild();
+    }
+
+    /// Wake and remove the waker which was registered first, i.e. the task which waits the longest.
+    fn wake_first(&self, probe: &impl ChurnProbe) {
+        let mut registered = self.registered.borrow_mut();
+        if registered.is_empty() {
+            return;
+        }
+
+        let waiter = registered.remove(0);
+        drop(registered);
+        self.rebuild();
+
+        probe.woken(WakerId::of(&waiter.waker));
+        waiter.waker.wake();
+    }
+
+    /// Replace `waker_registration` with one which only holds the wakers in `registered`.
+    fn rebuild(&self) {
+        let mut waker_registration = MultiWakerRegistration::new();
+        for waiter in self.registered.borrow().iter() {
+            waker_registration.register(&waiter.waker);
+        }
+        *self.waker_registration.borrow_mut() = waker_registration;
+    }
+
+    /// Register the waker of a wait behind the ones which are already waiting and return the id of the wait.
+    /// A wait which is still stored (`id`) keeps its place.
+    fn register(
+        &self,
+        id: Option<u32>,
+        task: WakerId,
+        waker: &Waker,
+        probe: &impl ChurnProbe,
+    ) -> u32 {
+        probe.registered(task);
+
+        let stored = self
+            .registered
+            .borrow_mut()
+            .iter_mut()
+            .find(|waiter| Some(waiter.id) == id)
+            .map(|waiter| {
+                waiter.waker.clone_from(waker);
+                waiter.id
+            });
+        let id = stored.unwrap_or_else(|| {
+            // A full registration wakes everyone to make room
+            if self.registered.borrow().is_full() {
+                for evicted in self.registered.borrow().iter() {
+                    probe.replaced(WakerId::of(&evicted.waker));
+                }
+                self.wake_registered(probe);
+            }
+
+            let id = self.next_id.get();
+            self.next_id.set(id.wrapping_add(1));
+            let _ = self.registered.borrow_mut().push(Waiter {
+                id,
+                waker: waker.clone(),
+            });
+            id
+        });
+        self.waker_registration.borrow_mut().register(waker);
+        id
+    }
+}
+
+/// Stores up to `N` wakers and wakes all of them on a change.
+/// If more than `N` tasks register, all stored wakers are woken to make room.
+/// `M` protects the state and the wakers, see [`SingleSlotSignal`](crate::SingleSlotSignal).
+pub struct MultiWakerRegistrationSignal<const N: usize, P = (), M = ThreadModeRawMutex, T = State> {
+    inner: Mutex<M, Inner<N, T>>,
+    probe: P,
+}
+
+impl<const N: usize, M: RawMutex> MultiWakerRegistrationSignal<N, (), M> {
+    pub const fn new() -> Self {
+        Self::with_probe(())
+    }
+}
+
+impl<const N: usize, P: ChurnProbe, M: RawMutex> MultiWakerRegistrationSignal<N, P, M> {
+    /// Create a signal which reports its churn to `probe`.
+    pub const fn with_probe(probe: P) -> Self {
+        Self::with_initial(State::NotReady, probe)
+    }
+}
+
+impl<const N: usize, P: ChurnProbe, M: RawMutex, T> MultiWakerRegistrationSignal<N, P, M, T> {
+    /// Create a signal with a custom payload, starting at `initial`.
+    pub const fn with_initial(initial: T, probe: P) -> Self {
+        Self {
+            inner: Mutex::new(Inner {
+                state: RefCell::new(initial),
+                waker_registration: RefCell::new(MultiWakerRegistration::new()),
+                registered: RefCell::new(Vec::new()),
+                next_id: Cell::new(0),
+                handed: Cell::new(None),
+                closed: Cell::new(false),
+            }),
+            probe,
+        }
+    }
+}
+
+impl<const N: usize, M: RawMutex, T: Default> Default
+    for MultiWakerRegistrationSignal<N, (), M, T>
+{
+    fn default() -> Self {
+        Self::with_initial(T::default(), ())
+    }
+}
+
+impl<const N: usize, P: ChurnProbe, M: RawMutex, T: Clone + PartialEq> ChangeSignal<T>
+    for MultiWakerRegistrationSignal<N, P, M, T>
+{
+    fn with<R>(&self, f: impl FnOnce(&T) -> R) -> R {
+        self.inner.lock(|s| f(&s.state.borrow()))
+    }
+
+    fn set(&self, value: T) {
+        self.inner.lock(|s| {
+            *s.state.borrow_mut() = value;
+            s.waker_registration.borrow_mut().wake();
+            s.wake_registered(&self.probe);
+        });
+    }
+
+    fn close(&self) {
+        self.inner.lock(|s| {
+            s.closed.set(true);
+            s.waker_registration.borrow_mut().wake();
+            s.wake_registered(&self.probe);
+        });
+    }
+
+    fn is_closed(&self) -> bool {
+        self.inner.lock(|s| s.closed.get())
+    }
+
+    async fn wait_changed(&self, current: &T) -> Result<T, Closed> {
+        let mut first_poll = true;
+        // The waiter this wait stored, so a dropped wait can remove it again
+        let registered = Cell::new(None);
+        let _unregister = OnDrop::new(|| {
+            if let Some(id) = registered.get() {
+                self.inner.lock(|s| s.unregister(id));
+            }
+        });
+
+        poll_fn(|cx| {
+            let task = WakerId::of(cx.waker());
+            trace!("Running waker of task: {}", task_name(task));
+            let first = core::mem::replace(&mut first_poll, false);
+
+            self.inner.lock(|s| {
+                if s.closed.get() {
+                    registered.set(None);
+                    return Poll::Ready(Err(Closed));
+                }
+
+                let state = s.state.borrow();
+                if *state != *current {
+                    // The waiter is still stored if the task was polled for another reason, e.g. another wait of a `select`
+                    if let Some(id) = registered.take() {
+                        s.unregister(id);
+                    }
+                    info!("{}: Signal is ready", task_name(task));
+                    self.probe.completed(task);
+                    Poll::Ready(Ok(state.clone()))
+                } else {
+                    info!("{}: Signal not ready, registering waker", task_name(task));
+                    if !first {
+                        self.probe.spurious_poll(task);
+                    }
+                    let id = s.register(registered.get(), task, cx.waker(), &self.probe);
+                    registered.set(Some(id));
+                    Poll::Pending
+                }
+            })
+        })
+        .await
+    }
+}
+
+/// The wakers are stored in the order the tasks started waiting, so `notify_one` wakes the oldest one.
+impl<const N: usize, P: ChurnProbe, M: RawMutex, T: Clone + PartialEq> HandOffSignal<T>
+    for MultiWakerRegistrationSignal<N, P, M, T>
+{
+    fn notify_one(&self, value: T) -> Option<T> {
+        self.inner.lock(|s| {
+            *s.state.borrow_mut() = value.clone();
+            let replaced = s.handed.replace(Some(value));
+            s.wake_first(&self.probe);
+            replaced
+        })
+    }
+
+    async fn wait_handed(&self) -> Result<T, Closed> {
+        // The waiter this wait stored, so a dropped wait can remove it again
+        let registered = Cell::new(None);
+        let _unregister = OnDrop::new(|| {
+            if let Some(id) = registered.get() {
+                self.inner.lock(|s| s.unregister(id));
+            }
+        });
+
+        poll_fn(|cx| {
+            let task = WakerId::of(cx.waker());
+
+            self.inner.lock(|s| {
+                if s.closed.get() {
+                    registered.set(None);
+                    return Poll::Ready(Err(Closed));
+                }
+
+                if let Some(state) = s.handed.take() {
+                    // The task may come back with a waker stored by an earlier poll
+                    if let Some(id) = registered.take() {
+                        s.unregister(id);
+                    }
+                    self.probe.completed(task);
+                    Poll::Ready(Ok(state))
+                } else {
+                    let id = s.register(registered.get(), task, cx.waker(), &self.probe);
+                    registered.set(Some(id));
+                    Poll::Pending
+                }
+            })
+        })
+        .await
+    }
+}
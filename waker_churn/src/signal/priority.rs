@@ -0,0 +1,566 @@
+use core::cell::{Cell, RefCell};
+use core::future::poll_fn;
+use core::task::{Poll, Waker};
+
+use embassy_sync::blocking_mutex::Mutex;
+use embassy_sync::blocking_mutex::raw::{RawMutex, ThreadModeRawMutex};
+use heapless::Vec;
+
+use crate::signal::OnDrop;
+use crate::stats::ChurnProbe;
+use crate::{ChangeSignal, Closed, HandOffSignal, State, WakerId};
+
+/// Priority of a waiter. Waiters with a higher priority are woken first.
+pub type Priority = u8;
+
+/// Priority of the waits of [`ChangeSignal`] and [`HandOffSignal`], which don't take one.
+pub const DEFAULT_PRIORITY: Priority = 0;
+
+struct Waiter {
+    waker: Waker,
+    priority: Priority,
+    /// Order of the registration, so waiters of the same priority are served in FIFO order.
+    /// Also identifies the wait, a task may wait several times at once, e.g. in a `select`
+    seq: u32,
+}
+
+struct Inner<const N: usize> {
+    waiters: Vec<Waiter, N>,
+    next_seq: u32,
+    closed: bool,
+}
+
+impl<const N: usize> Inner<N> {
+    /// Wake all waiters highest priority first, waiters of the same priority in FIFO order.
+    fn wake_all(&mut self) {
+        self.waiters
+            .sort_unstable_by(|a, b| b.priority.cmp(&a.priority).then_with(|| a.seq.cmp(&b.seq)));
+
+        for waiter in self.waiters.iter() {
+            waiter.waker.wake_by_ref();
+        }
+        self.waiters.clear();
+    }
+}
+
+/// Wait queue whose waiters register with a [`Priority`].
+///
+/// Like `maitake_sync::WaitQueue`, but [`wake_all`](PriorityWaitQueue::wake_all) wakes the waiters highest priority first,
+/// and [`wake`](PriorityWaitQueue::wake) picks the waiter with the highest priority.
+/// Waiters of the same priority are served in FIFO order.
+/// The order the tasks are polled in is up to the executor: a FIFO run queue like the one of maitake polls them in the
+/// order of the wakes, while the run queue of `embassy_executor` polls the tasks which were woken together in reverse.
+/// The wakers are stored in a `heapless::Vec`, so at most `N` waits can be stored. If more register, all stored wakers are woken to make room.
+pub struct PriorityWaitQueue<const N: usize, M = ThreadModeRawMutex> {
+    inner: Mutex<M, RefCell<Inner<N>>>,
+}
+
+impl<const N: usize, M: RawMutex> PriorityWaitQueue<N, M> {
+    pub const fn new() -> Self {
+        Self {
+            inner: Mutex::new(RefCell::new(Inner {
+                waiters: Vec::new(),
+                next_seq: 0,
+                closed: false,
+            })),
+        }
+    }
+
+    /// Wake the waiter with the highest priority. Returns false if nobody is waiting.
+    ///
+    /// Unlike `WaitQueue::wake`, the wakeup is not stored if nobody is waiting, the condition of the next `wait_for` decides.
+    pub fn wake(&self) -> bool {
+        self.inner.lock(|inner| {
+            let mut inner = inner.borrow_mut();
+            let highest = inner
+                .waiters
+                .iter()
+                .enumerate()
+                .max_by(|(_, a), (_, b)| {
+                    a.priority.cmp(&b.priority).then_with(|| b.seq.cmp(&a.seq))
+                })
+                .map(|(i, _)| i);
+
+            match highest {
+                Some(i) => {
+                    inner.waiters.swap_remove(i).waker.wake();
+                    true
+                }
+                None => false,
+            }
+        })
+    }
+
+    /// Wake all waiters, highest priority first.
+    pub fn wake_all(&self) {
+        self.inner.lock(|inner| inner.borrow_mut().wake_all());
+    }
+
+    /// Close the queue and wake all waiters. Current and future waits return [`Closed`].
+    pub fn close(&self) {
+        self.inner.lock(|inner| {
+            let mut inner = inner.borrow_mut();
+            inner.closed = true;
+            inner.wake_all();
+        });
+    }
+
+    pub fn is_closed(&self) -> bool {
+        self.inner.lock(|inner| inner.borrow().closed)
+    }
+
+    /// Wait with `priority` until `f` returns true.
+    pub async fn wait_for(
+        &self,
+        priority: Priority,
+        mut f: impl FnMut() -> bool,
+    ) -> Result<(), Closed> {
+        self.wait_for_value(priority, || f().then_some(())).await
+    }
+
+    /// Wait with `priority` until `f` returns a value.
+    ///
+    /// The waker is registered before `f` is checked, so a wake in between is not lost.
+    pub async fn wait_for_value<T>(
+        &self,
+        priority: Priority,
+        mut f: impl FnMut() -> Option<T>,
+    ) -> Result<T, Closed> {
+        // The waiter this wait stored, so a dropped wait can remove it again
+        let registered = Cell::new(None);
+        let _unregister = OnDrop::new(|| {
+            if let Some(seq) = registered.get() {
+                self.unregister(seq);
+            }
+        });
+
+        poll_fn(|cx| {
+            match self.register(registered.get(), cx.waker(), priority) {
+                Ok(seq) => registered.set(Some(seq)),
+                Err(Closed) => {
+                    registered.set(None);
+                    return Poll::Ready(Err(Closed));
+                }
+            }
+
+            match f() {
+                Some(value) => {
+                    if let Some(seq) = registered.take() {
+                        self.unregister(seq);
+                    }
+                    Poll::Ready(Ok(value))
+                }
+                None => Poll::Pending,
+            }
+        })
+        .await
+    }
+
+    /// Store the waker of a wait and return its sequence number. A wait which is still stored (`seq`) keeps its place.
+    fn register(&self, seq: Option<u32>, waker: &Waker, priority: Priority) -> Result<u32, Closed> {
+        self.inner.lock(|inner| {
+            let mut inner = inner.borrow_mut();
+            if inner.closed {
+                return Err(Closed);
+            }
+
+            if let Some(waiter) = inner
+                .waiters
+                .iter_mut()
+                .find(|waiter| Some(waiter.seq) == seq)
+            {
+                waiter.waker.clone_from(waker);
+                waiter.priority = priority;
+                return Ok(waiter.seq);
+            }
+
+            if inner.waiters.is_full() {
+                inner.wake_all();
+            }
+
+            let seq = inner.next_seq;
+            inner.next_seq = seq.wrapping_add(1);
+            let _ = inner.waiters.push(Waiter {
+                waker: waker.clone(),
+                priority,
+                seq,
+            });
+            Ok(seq)
+        })
+    }
+
+    fn unregister(&self, seq: u32) {
+        self.inner.lock(|inner| {
+            inner
+                .borrow_mut()
+                .waiters
+                .retain(|waiter| waiter.seq != seq);
+        });
+    }
+}
+
+impl<const N: usize, M: RawMutex> Default for PriorityWaitQueue<N, M> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Like [`WaitQueueSignal`](crate::WaitQueueSignal), but on a [`PriorityWaitQueue`], so it can replace it in the demos.
+///
+/// The waits of [`ChangeSignal`] and [`HandOffSignal`] use [`DEFAULT_PRIORITY`]. Use the `_with_priority` variants or
+/// [`at_priority`](PrioritySignal::at_priority), so e.g. the time critical consumer of a change is woken before the logging.
+pub struct PrioritySignal<const N: usize, P = (), M = ThreadModeRawMutex, T = State> {
+    state: Mutex<M, RefCell<T>>,
+    /// Update of `notify_one` which no waiter took yet
+    handed: Mutex<M, Cell<Option<T>>>,
+    waker_registration: PriorityWaitQueue<N, M>,
+    probe: P,
+}
+
+impl<const N: usize, M: RawMutex> PrioritySignal<N, (), M> {
+    pub const fn new() -> Self {
+        Self::with_probe(())
+    }
+}
+
+impl<const N: usize, P: ChurnProbe, M: RawMutex> PrioritySignal<N, P, M> {
+    /// Create a signal which reports its churn to `probe`.
+    pub const fn with_probe(probe: P) -> Self {
+        Self::with_initial(State::NotReady, probe)
+    }
+}
+
+impl<const N: usize, P: ChurnProbe, M: RawMutex, T: Clone + PartialEq> PrioritySignal<N, P, M, T> {
+    /// Create a signal with a custom payload, starting at `initial`.
+    pub const fn with_initial(initial: T, probe: P) -> Self {
+        Self {
+            state: Mutex::new(RefCell::new(initial)),
+            handed: Mutex::new(Cell::new(None)),
+            waker_registration: PriorityWaitQueue::new(),
+            probe,
+        }
+    }
+
+    /// [`ChangeSignal::wait_changed`] with the given priority.
+    pub async fn wait_changed_with_priority(
+        &self,
+        current: &T,
+        priority: Priority,
+    ) -> Result<T, Closed> {
+        let task = WakerId::current().await;
+        let mut first_check = true;
+
+        // The state is returned from the same lock which found it changed
+        self.waker_registration
+            .wait_for_value(priority, || {
+                let first = core::mem::replace(&mut first_check, false);
+                if !first {
+                    self.probe.woken(task);
+                }
+
+                let changed = self.with(|state| (state != current).then(|| state.clone()));
+                if changed.is_some() {
+                    self.probe.completed(task);
+                } else {
+                    if !first {
+                        self.probe.spurious_poll(task);
+                    }
+                    self.probe.registered(task);
+                }
+                changed
+            })
+            .await
+    }
+
+    /// [`HandOffSignal::wait_handed`] with the given priority.
+    pub async fn wait_handed_with_priority(&self, priority: Priority) -> Result<T, Closed> {
+        let task = WakerId::current().await;
+
+        let state = self
+            .waker_registration
+            .wait_for_value(priority, || self.handed.lock(|s| s.take()))
+            .await?;
+
+        self.probe.completed(task);
+        Ok(state)
+    }
+
+    /// View of the signal whose waits use `priority`, e.g. to pass it to [`wait_for_signal`](crate::wait_for_signal).
+    pub fn at_priority(&self, priority: Priority) -> AtPriority<'_, N, P, M, T> {
+        AtPriority {
+            signal: self,
+            priority,
+        }
+    }
+}
+
+impl<const N: usize, M: RawMutex, T: Clone + PartialEq + Default> Default
+    for PrioritySignal<N, (), M, T>
+{
+    fn default() -> Self {
+        Self::with_initial(T::default(), ())
+    }
+}
+
+impl<const N: usize, P: ChurnProbe, M: RawMutex, T: Clone + PartialEq> ChangeSignal<T>
+    for PrioritySignal<N, P, M, T>
+{
+    fn with<R>(&self, f: impl FnOnce(&T) -> R) -> R {
+        self.state.lock(|s| f(&s.borrow()))
+    }
+
+    fn set(&self, value: T) {
+        self.state.lock(|s| *s.borrow_mut() = value);
+        self.waker_registration.wake_all();
+    }
+
+    fn close(&self) {
+        self.waker_registration.close();
+    }
+
+    fn is_closed(&self) -> bool {
+        self.waker_registration.is_closed()
+    }
+
+    async fn wait_changed(&self, current: &T) -> Result<T, Closed> {
+        self.wait_changed_with_priority(current, DEFAULT_PRIORITY)
+            .await
+    }
+}
+
+impl<const N: usize, P: ChurnProbe, M: RawMutex, T: Clone + PartialEq> HandOffSignal<T>
+    for PrioritySignal<N, P, M, T>
+{
+    fn notify_one(&self, value: T) -> Option<T> {
+        self.state.lock(|s| *s.borrow_mut() = value.clone());
+        let replaced = self.handed.lock(|s| s.replace(Some(value)));
+        self.waker_registration.wake();
+        replaced
+    }
+
+    async fn wait_handed(&self) -> Result<T, Closed> {
+        self.wait_handed_with_priority(DEFAULT_PRIORITY).await
+    }
+}
+
+/// A [`PrioritySignal`] whose waits use a fixed priority. Created by [`PrioritySignal::at_priority`].
+pub struct AtPriority<'a, const N: usize, P = (), M: RawMutex = ThreadModeRawMutex, T = State> {
+    signal: &'a PrioritySignal<N, P, M, T>,
+    priority: Priority,
+}
+
+impl<const N: usize, P: ChurnProbe, M: RawMutex, T: Clone + PartialEq> ChangeSignal<T>
+    for AtPriority<'_, N, P, M, T>
+{
+    fn with<R>(&self, f: impl FnOnce(&T) -> R) -> R {
+        self.signal.with(f)
+    }
+
+    fn set(&self, value: T) {
+        self.signal.set(value);
+    }
+
+    fn close(&self) {
+        self.signal.close();
+    }
+
+    fn is_closed(&self) -> bool {
+        self.signal.is_closed()
+    }
+
+    async fn wait_changed(&self, current: &T) -> Result<T, Closed> {
+        self.signal
+            .wait_changed_with_priority(current, self.priority)
+            .await
+    }
+}
+
+impl<const N: usize, P: ChurnProbe, M: RawMutex, T: Clone + PartialEq> HandOffSignal<T>
+    for AtPriority<'_, N, P, M, T>
+{
+    fn notify_one(&self, value: T) -> Option<T> {
+        self.signal.notify_one(value)
+    }
+
+    async fn wait_handed(&self) -> Result<T, Closed> {
+        self.signal.wait_handed_with_priority(self.priority).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use core::cell::RefCell;
+    use core::pin::pin;
+    use core::task::Context;
+
+    use super::*;
+    use crate::sim::{RunOrder, SimEvent, Simulator, TaskId};
+    use crate::test_util::{CountingWaker, in_thread_mode};
+    use crate::wait_for_signal;
+
+    /// The tasks whose wakers were called after `from`, in the order of the wakes.
+    fn woken_after(sim: &Simulator, from: u64) -> std::vec::Vec<TaskId> {
+        sim.events()
+            .into_iter()
+            .filter_map(|event| match event {
+                SimEvent::Wake { task, at, .. } if at >= from => Some(task),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// The tasks which were polled after `from`, in the order of the polls.
+    fn polled_after(sim: &Simulator, from: u64) -> std::vec::Vec<TaskId> {
+        sim.events()
+            .into_iter()
+            .filter_map(|event| match event {
+                SimEvent::Poll { task, at, .. } if at >= from => Some(task),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// Spawn a logging, a control and a UI consumer on `signal`, publish a change and return the tasks in the order
+    /// they were woken and polled, together with the tasks ordered highest priority first.
+    fn run_consumers(order: RunOrder) -> [std::vec::Vec<TaskId>; 3] {
+        let signal: PrioritySignal<3> = PrioritySignal::new();
+        let (logging, control, ui) = (
+            signal.at_priority(1),
+            signal.at_priority(9),
+            signal.at_priority(5),
+        );
+
+        let mut sim = Simulator::new(order);
+        let task_three = sim.spawn("TaskThree", wait_for_signal("TaskThree", &logging, true));
+        let task_one = sim.spawn("TaskOne", wait_for_signal("TaskOne", &control, true));
+        let task_two = sim.spawn("TaskTwo", wait_for_signal("TaskTwo", &ui, true));
+
+        sim.run_for(10);
+        let now = sim.now();
+        signal.set(State::Ready(1));
+        sim.run_for(10);
+
+        [
+            woken_after(&sim, now),
+            polled_after(&sim, now),
+            vec![task_one, task_two, task_three],
+        ]
+    }
+
+    #[test]
+    fn test_woken_highest_first() {
+        in_thread_mode(|| {
+            // The time critical consumer is woken first, although it registered after the logging
+            for order in [RunOrder::Fifo, RunOrder::Embassy] {
+                let [woken, _, highest_first] = run_consumers(order);
+                assert_eq!(woken, highest_first);
+            }
+        });
+    }
+
+    #[test]
+    fn test_polled_in_order_of_the_run_queue() {
+        in_thread_mode(|| {
+            // A FIFO run queue polls the tasks in the order of the wakes
+            let [_, polled, highest_first] = run_consumers(RunOrder::Fifo);
+            assert_eq!(polled, highest_first);
+
+            // The run queue of embassy polls the tasks which were woken together in reverse
+            let [_, mut polled, highest_first] = run_consumers(RunOrder::Embassy);
+            polled.reverse();
+            assert_eq!(polled, highest_first);
+        });
+    }
+
+    #[test]
+    fn test_waits_of_one_task_are_separate() {
+        in_thread_mode(|| {
+            let queue: PriorityWaitQueue<2> = PriorityWaitQueue::new();
+            let (counter, waker) = CountingWaker::new();
+            let mut cx = Context::from_waker(&waker);
+
+            // Like both branches of a `select`
+            let mut low = pin!(queue.wait_for(1, || false));
+            let mut high = pin!(queue.wait_for(5, || false));
+            assert_eq!(low.as_mut().poll(&mut cx), Poll::Pending);
+            assert_eq!(high.as_mut().poll(&mut cx), Poll::Pending);
+
+            // Both are stored, and polling one again keeps the other
+            assert_eq!(high.as_mut().poll(&mut cx), Poll::Pending);
+            assert!(queue.wake());
+            assert!(queue.wake());
+            assert!(!queue.wake());
+            assert_eq!(counter.wakes(), 2);
+        });
+    }
+
+    #[test]
+    fn test_notify_one_picks_highest() {
+        in_thread_mode(|| {
+            let signal: PrioritySignal<3> = PrioritySignal::new();
+            let taken = RefCell::new(std::vec::Vec::new());
+
+            let worker = |name: &'static str, priority: Priority, jobs: usize| {
+                let (signal, taken) = (&signal, &taken);
+                async move {
+                    for _ in 0..jobs {
+                        let Ok(state) = signal.wait_handed_with_priority(priority).await else {
+                            return;
+                        };
+                        taken.borrow_mut().push((name, state));
+                    }
+                }
+            };
+
+            // FIFO, so the workers register in the order they are spawned
+            let mut sim = Simulator::new(RunOrder::Fifo);
+            sim.spawn("TaskThree", worker("TaskThree", 1, 2));
+            sim.spawn("TaskOne", worker("TaskOne", 9, 2));
+            sim.spawn("TaskTwo", worker("TaskTwo", 1, 2));
+
+            for counter in 1..=4 {
+                sim.run_for(10);
+                signal.notify_one(State::Ready(counter));
+            }
+            sim.run_for(10);
+
+            // TaskOne takes every job until it is done, then the waiters of the same priority take turns
+            drop(sim);
+            assert_eq!(
+                taken.into_inner(),
+                [
+                    ("TaskOne", State::Ready(1)),
+                    ("TaskOne", State::Ready(2)),
+                    ("TaskThree", State::Ready(3)),
+                    ("TaskTwo", State::Ready(4)),
+                ]
+            );
+        });
+    }
+
+    #[test]
+    fn test_same_priority_is_fifo() {
+        in_thread_mode(|| {
+            let queue: PriorityWaitQueue<3> = PriorityWaitQueue::new();
+            // FIFO, so the tasks register in the order they are spawned
+            let mut sim = Simulator::new(RunOrder::Fifo);
+            let first = sim.spawn("TaskOne", async {
+                let _ = queue.wait_for(2, || false).await;
+            });
+            let second = sim.spawn("TaskTwo", async {
+                let _ = queue.wait_for(2, || false).await;
+            });
+            let high = sim.spawn("TaskThree", async {
+                let _ = queue.wait_for(7, || false).await;
+            });
+            sim.run_for(10);
+
+            let now = sim.now();
+            for _ in 0..3 {
+                assert!(queue.wake());
+            }
+            assert!(!queue.wake());
+            assert_eq!(woken_after(&sim, now), [high, first, second]);
+        });
+    }
+}
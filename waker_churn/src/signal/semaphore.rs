@@ -0,0 +1,316 @@
+use core::cell::{Cell, RefCell};
+use core::future::poll_fn;
+use core::task::{Poll, Waker};
+
+use embassy_sync::blocking_mutex::Mutex;
+use embassy_sync::blocking_mutex::raw::{RawMutex, ThreadModeRawMutex};
+use heapless::Vec;
+
+use crate::WakerId;
+use crate::names::task_name;
+use crate::signal::OnDrop;
+
+/// Error of [`Semaphore::acquire`] if `N` tasks are already waiting.
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct QueueFull;
+
+/// A task which waits for permits.
+struct Waiter {
+    /// Identifies the `acquire` call, a task may wait in several of them
+    id: u32,
+    waker: Waker,
+    permits: usize,
+    /// The permits were taken from `available` for this waiter, which owns them now
+    granted: bool,
+}
+
+struct Inner<const N: usize> {
+    available: usize,
+    /// The waiters in the order they started waiting
+    waiters: Vec<Waiter, N>,
+    next_id: u32,
+}
+
+impl<const N: usize> Inner<N> {
+    /// Hand the available permits to the waiters in FIFO order.
+    ///
+    /// Stops at the first waiter which needs more permits than available, so a large request is not starved by smaller ones.
+    fn grant(&mut self) {
+        for waiter in self.waiters.iter_mut() {
+            if waiter.granted {
+                continue;
+            }
+            if waiter.permits > self.available {
+                break;
+            }
+            self.available -= waiter.permits;
+            waiter.granted = true;
+            waiter.waker.wake_by_ref();
+        }
+    }
+
+    fn release(&mut self, permits: usize) {
+        self.available += permits;
+        self.grant();
+    }
+
+    /// Remove a waiter whose `acquire` was dropped, and give back the permits it was granted.
+    fn cancel(&mut self, id: u32) {
+        let Some(i) = self.waiters.iter().position(|waiter| waiter.id == id) else {
+            return;
+        };
+
+        let waiter = self.waiters.remove(i);
+        if waiter.granted {
+            self.available += waiter.permits;
+        }
+        // Also without permits to give back, the removed waiter may have blocked the ones behind it
+        self.grant();
+    }
+}
+
+/// Counting semaphore which hands out its permits in FIFO order, to limit the tasks using a shared resource at once
+/// (e.g. DMA channels).
+///
+/// Up to `N` tasks wait for permits in the order they called [`acquire`](Semaphore::acquire). A task which needs more permits than
+/// available also blocks the tasks behind it, even if they need less, so it can't be starved.
+/// If `N` tasks are already waiting, `acquire` returns [`QueueFull`] instead of queueing the task, so `N` has to cover
+/// all tasks which may wait at once.
+/// The permits are returned when the [`SemaphorePermit`] is dropped, and an `acquire` which is dropped while it waits
+/// leaves the queue and returns the permits it was already granted.
+/// `M` protects the permits and the waiters, see [`SingleSlotSignal`](crate::SingleSlotSignal).
+pub struct Semaphore<const N: usize, M = ThreadModeRawMutex> {
+    inner: Mutex<M, RefCell<Inner<N>>>,
+}
+
+impl<const N: usize, M: RawMutex> Semaphore<N, M> {
+    pub const fn new(permits: usize) -> Self {
+        Self {
+            inner: Mutex::new(RefCell::new(Inner {
+                available: permits,
+                waiters: Vec::new(),
+                next_id: 0,
+            })),
+        }
+    }
+
+    /// Number of permits which are not held and not granted to a waiter.
+    pub fn available_permits(&self) -> usize {
+        self.inner.lock(|inner| inner.borrow().available)
+    }
+
+    /// Add permits, e.g. when another instance of the resource becomes usable.
+    pub fn add_permits(&self, permits: usize) {
+        self.inner.lock(|inner| inner.borrow_mut().release(permits));
+    }
+
+    /// Take `permits` permits without waiting.
+    ///
+    /// Fails if other tasks are waiting, even if enough permits are available, because they are first in line.
+    pub fn try_acquire(&self, permits: usize) -> Option<SemaphorePermit<'_, N, M>> {
+        self.inner.lock(|inner| {
+            let mut inner = inner.borrow_mut();
+            if !inner.waiters.is_empty() || inner.available < permits {
+                return None;
+            }
+            inner.available -= permits;
+            Some(SemaphorePermit {
+                semaphore: self,
+                permits,
+            })
+        })
+    }
+
+    /// Wait until `permits` permits are granted to this task.
+    ///
+    /// Fails with [`QueueFull`] if the task would have to wait, but `N` tasks are already waiting.
+    /// A request for more permits than the semaphore will ever have waits forever, and blocks the tasks behind it.
+    pub async fn acquire(&self, permits: usize) -> Result<SemaphorePermit<'_, N, M>, QueueFull> {
+        // The waiter this call stored, so a dropped acquire can leave the queue
+        let waiting = Cell::new(None);
+        let _cancel = OnDrop::new(|| {
+            if let Some(id) = waiting.get() {
+                self.inner.lock(|inner| inner.borrow_mut().cancel(id));
+            }
+        });
+
+        poll_fn(|cx| {
+            let task = WakerId::of(cx.waker());
+
+            self.inner.lock(|inner| {
+                let mut inner = inner.borrow_mut();
+
+                if let Some(id) = waiting.get() {
+                    let i = inner
+                        .waiters
+                        .iter()
+                        .position(|waiter| waiter.id == id)
+                        .expect("waiter is stored until it is granted or cancelled");
+                    let waiter = &mut inner.waiters[i];
+                    if waiter.granted {
+                        inner.waiters.remove(i);
+                        waiting.set(None);
+                        return Poll::Ready(Ok(()));
+                    }
+                    if !waiter.waker.will_wake(cx.waker()) {
+                        waiter.waker = cx.waker().clone();
+                    }
+                    return Poll::Pending;
+                }
+
+                if inner.waiters.is_empty() && inner.available >= permits {
+                    inner.available -= permits;
+                    return Poll::Ready(Ok(()));
+                }
+
+                let id = inner.next_id;
+                let waiter = Waiter {
+                    id,
+                    waker: cx.waker().clone(),
+                    permits,
+                    granted: false,
+                };
+                if inner.waiters.push(waiter).is_err() {
+                    warn!("{}: Semaphore queue is full", task_name(task));
+                    return Poll::Ready(Err(QueueFull));
+                }
+
+                trace!("{}: Waiting for {} permits", task_name(task), permits);
+                inner.next_id = id.wrapping_add(1);
+                waiting.set(Some(id));
+                Poll::Pending
+            })
+        })
+        .await?;
+
+        Ok(SemaphorePermit {
+            semaphore: self,
+            permits,
+        })
+    }
+}
+
+/// Permits of a [`Semaphore`], which are returned when it is dropped.
+#[must_use = "the permits are returned right away if they are not held"]
+pub struct SemaphorePermit<'a, const N: usize, M: RawMutex = ThreadModeRawMutex> {
+    semaphore: &'a Semaphore<N, M>,
+    permits: usize,
+}
+
+impl<const N: usize, M: RawMutex> SemaphorePermit<'_, N, M> {
+    /// Number of permits held.
+    pub fn permits(&self) -> usize {
+        self.permits
+    }
+}
+
+impl<const N: usize, M: RawMutex> Drop for SemaphorePermit<'_, N, M> {
+    fn drop(&mut self) {
+        self.semaphore
+            .inner
+            .lock(|inner| inner.borrow_mut().release(self.permits));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use core::pin::pin;
+    use core::task::Context;
+
+    use super::*;
+    use crate::test_util::{CountingWaker, in_thread_mode};
+
+    #[test]
+    fn test_fifo_fair() {
+        in_thread_mode(|| {
+            let semaphore: Semaphore<2> = Semaphore::new(3);
+            let held = semaphore.try_acquire(2).unwrap();
+            let (large_counter, large_waker) = CountingWaker::new();
+            let (small_counter, small_waker) = CountingWaker::new();
+            let mut large_cx = Context::from_waker(&large_waker);
+            let mut small_cx = Context::from_waker(&small_waker);
+
+            // One permit is available, but the large request is first in line
+            let mut large = pin!(semaphore.acquire(3));
+            let mut small = pin!(semaphore.acquire(1));
+            assert!(large.as_mut().poll(&mut large_cx).is_pending());
+            assert!(small.as_mut().poll(&mut small_cx).is_pending());
+            assert!(semaphore.try_acquire(1).is_none());
+            assert_eq!(semaphore.available_permits(), 1);
+
+            drop(held);
+            assert_eq!((large_counter.wakes(), small_counter.wakes()), (1, 0));
+            let Poll::Ready(Ok(large_permit)) = large.poll(&mut large_cx) else {
+                panic!("large request not granted");
+            };
+            assert_eq!(large_permit.permits(), 3);
+            assert!(small.as_mut().poll(&mut small_cx).is_pending());
+
+            drop(large_permit);
+            assert_eq!(small_counter.wakes(), 1);
+            assert!(matches!(small.poll(&mut small_cx), Poll::Ready(Ok(_))));
+            assert_eq!(semaphore.available_permits(), 3);
+        });
+    }
+
+    #[test]
+    fn test_full_queue_is_an_error() {
+        in_thread_mode(|| {
+            let semaphore: Semaphore<1> = Semaphore::new(1);
+            let held = semaphore.try_acquire(1).unwrap();
+            let (counter, waker) = CountingWaker::new();
+            let mut cx = Context::from_waker(&waker);
+
+            let mut first = pin!(semaphore.acquire(1));
+            assert!(first.as_mut().poll(&mut cx).is_pending());
+
+            // The second task is told right away instead of polling itself until a slot is free
+            let second = pin!(semaphore.acquire(1));
+            assert!(matches!(second.poll(&mut cx), Poll::Ready(Err(QueueFull))));
+            assert_eq!(counter.wakes(), 0);
+
+            // The first one keeps its place
+            drop(held);
+            assert_eq!(counter.wakes(), 1);
+            assert!(matches!(first.poll(&mut cx), Poll::Ready(Ok(_))));
+        });
+    }
+
+    #[test]
+    fn test_cancel_safe() {
+        in_thread_mode(|| {
+            let semaphore: Semaphore<2> = Semaphore::new(1);
+            let held = semaphore.try_acquire(1).unwrap();
+            let (counter, waker) = CountingWaker::new();
+            let mut cx = Context::from_waker(&waker);
+
+            // A waiter which is dropped after its permits were granted returns them
+            {
+                let mut granted = pin!(semaphore.acquire(1));
+                assert!(granted.as_mut().poll(&mut cx).is_pending());
+                drop(held);
+                assert_eq!(counter.wakes(), 1);
+                assert_eq!(semaphore.available_permits(), 0);
+            }
+            assert_eq!(semaphore.available_permits(), 1);
+
+            // A dropped waiter at the head no longer blocks the ones behind it
+            let (small_counter, small_waker) = CountingWaker::new();
+            let mut small_cx = Context::from_waker(&small_waker);
+            let mut small = pin!(semaphore.acquire(1));
+            {
+                let mut large = pin!(semaphore.acquire(2));
+                let held = semaphore.try_acquire(1);
+                assert!(held.is_some());
+                assert!(large.as_mut().poll(&mut cx).is_pending());
+                assert!(small.as_mut().poll(&mut small_cx).is_pending());
+                drop(held);
+                assert_eq!(small_counter.wakes(), 0);
+            }
+            assert_eq!(small_counter.wakes(), 1);
+            assert!(matches!(small.poll(&mut small_cx), Poll::Ready(Ok(_))));
+            assert_eq!(semaphore.available_permits(), 1);
+        });
+    }
+}
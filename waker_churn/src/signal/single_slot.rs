@@ -0,0 +1,376 @@
+use core::cell::{Cell, RefCell};
+use core::future::poll_fn;
+use core::task::{Poll, Waker};
+
+use embassy_sync::blocking_mutex::Mutex;
+use embassy_sync::blocking_mutex::raw::{RawMutex, ThreadModeRawMutex};
+use embassy_sync::waitqueue::{AtomicWaker, WakerRegistration};
+
+use crate::names::task_name;
+use crate::signal::OnDrop;
+use crate::stats::ChurnProbe;
+use crate::{ChangeSignal, Closed, State, WakerId};
+
+/// Storage for exactly one waker.
+pub trait WakerSlot {
+    /// True if replacing a waker with a different one wakes the replaced waker.
+    const WAKES_ON_REPLACE: bool;
+    /// True if the waker is still stored after [`WakerSlot::wake`].
+    const KEEPS_WAKER_ON_WAKE: bool;
+
+    /// Store the waker, replacing the stored one.
+    fn register(&mut self, waker: &Waker);
+
+    /// Wake the stored waker, if any.
+    fn wake(&mut self);
+
+    /// Remove the stored waker without waking it.
+    fn clear(&mut self);
+}
+
+/// The naive slot: overwrites the stored waker and keeps it after waking.
+impl WakerSlot for Option<Waker> {
+    const WAKES_ON_REPLACE: bool = false;
+    const KEEPS_WAKER_ON_WAKE: bool = true;
+
+    fn register(&mut self, waker: &Waker) {
+        *self = Some(waker.clone());
+    }
+
+    fn wake(&mut self) {
+        if let Some(waker) = self {
+            info!("Wake {}!", task_name(WakerId::of(waker)));
+            waker.wake_by_ref();
+        } else {
+            info!("No waker");
+        }
+    }
+
+    fn clear(&mut self) {
+        *self = None;
+    }
+}
+
+impl WakerSlot for AtomicWaker {
+    const WAKES_ON_REPLACE: bool = false;
+    const KEEPS_WAKER_ON_WAKE: bool = true;
+
+    fn register(&mut self, waker: &Waker) {
+        AtomicWaker::register(self, waker);
+    }
+
+    fn wake(&mut self) {
+        AtomicWaker::wake(self);
+    }
+
+    /// `AtomicWaker` has no way to take the waker out, so replace it with an empty one.
+    fn clear(&mut self) {
+        *self = AtomicWaker::new();
+    }
+}
+
+impl WakerSlot for WakerRegistration {
+    const WAKES_ON_REPLACE: bool = true;
+    const KEEPS_WAKER_ON_WAKE: bool = false;
+
+    fn register(&mut self, waker: &Waker) {
+        WakerRegistration::register(self, waker);
+    }
+
+    fn wake(&mut self) {
+        WakerRegistration::wake(self);
+    }
+
+    fn clear(&mut self) {
+        *self = WakerRegistration::new();
+    }
+}
+
+struct Inner<S, T> {
+    state: RefCell<T>,
+    waker_registration: RefCell<S>,
+    /// The task whose waker is stored in `waker_registration`
+    registered: Cell<Option<WakerId>>,
+    /// Identifies the stored registration, so a wait can tell if the waker it stored is still there
+    registration: Cell<u32>,
+    /// Number of waits which stored their waker in the current registration. The waits of a task share its waker,
+    /// e.g. both branches of a `select`, so the waker is only removed when the last of them is dropped
+    waits: Cell<u32>,
+    closed: Cell<bool>,
+}
+
+impl<S: WakerSlot, T> Inner<S, T> {
+    /// Start a new registration, the waits of the previous one don't hold the slot anymore.
+    fn renew(&self) {
+        self.registration
+            .set(self.registration.get().wrapping_add(1));
+        self.waits.set(0);
+    }
+
+    /// Wake the stored waker and tell the probe which task it belongs to.
+    fn wake(&self, probe: &impl ChurnProbe) {
+        self.waker_registration.borrow_mut().wake();
+
+        let woken = if S::KEEPS_WAKER_ON_WAKE {
+            self.registered.get()
+        } else {
+            self.renew();
+            self.registered.take()
+        };
+        if let Some(task) = woken {
+            probe.woken(task);
+        }
+    }
+
+    /// Store the waker of a wait and return the registration it holds. A wait which already holds the current
+    /// registration (`held`) is not counted again.
+    fn register(
+        &self,
+        held: Option<u32>,
+        task: WakerId,
+        waker: &Waker,
+        probe: &impl ChurnProbe,
+    ) -> u32 {
+        if let Some(old) = self.registered.replace(Some(task))
+            && old != task
+        {
+            probe.replaced(old);
+            if S::WAKES_ON_REPLACE {
+                probe.woken(old);
+            }
+            self.renew();
+        }
+        if held != Some(self.registration.get()) {
+            self.waits.set(self.waits.get() + 1);
+        }
+        self.waker_registration.borrow_mut().register(waker);
+        self.registration.get()
+    }
+
+    /// A wait no longer holds `registration`. If it was the last one and the wait was dropped (`remove`),
+    /// the waker is removed as well, while a completed wait leaves it to the slot like before.
+    fn release(&self, registration: u32, remove: bool) {
+        if self.registration.get() != registration {
+            return;
+        }
+
+        let waits = self.waits.get().saturating_sub(1);
+        self.waits.set(waits);
+        if waits == 0 && remove {
+            self.waker_registration.borrow_mut().clear();
+            self.registered.set(None);
+            self.renew();
+        }
+    }
+}
+
+/// Signal which stores the waker of its waiters in a single [`WakerSlot`].
+///
+/// Whatever the slot does, only one waiter can be registered at a time. See the aliases for the behaviour of each slot.
+/// `M` protects the state and the slot. The default only allows thread mode, use `CriticalSectionRawMutex` to `set` from an interrupt.
+/// `T` is the payload, see [`ChangeSignal`].
+pub struct SingleSlotSignal<S, P = (), M = ThreadModeRawMutex, T = State> {
+    inner: Mutex<M, Inner<S, T>>,
+    probe: P,
+}
+
+/// Stores only one waker. If the waker gets replaced, the original waker will not be called.
+/// Therefore, only the task which registered its waker last will be woken up.
+pub type NaiveSignal<P = (), M = ThreadModeRawMutex, T = State> =
+    SingleSlotSignal<Option<Waker>, P, M, T>;
+
+/// The `AtomicWaker` is intentionally not designed for waking multiple tasks, but rather for waking a task from an interrupt.
+/// Only the task which registered its waker last will be woken up.
+pub type AtomicWakerSignal<P = (), M = ThreadModeRawMutex, T = State> =
+    SingleSlotSignal<AtomicWaker, P, M, T>;
+
+/// `WakerRegistration::register` wakes the previously stored waker if it is replaced.
+/// With two waiting tasks this ends up jumping between the two tasks, which needs a lot of polls until both saw the change.
+pub type WakerRegistrationSignal<P = (), M = ThreadModeRawMutex, T = State> =
+    SingleSlotSignal<WakerRegistration, P, M, T>;
+
+impl<S: WakerSlot, P: ChurnProbe, M: RawMutex> SingleSlotSignal<S, P, M> {
+    /// Create a signal from an empty slot which reports its churn to `probe`.
+    pub const fn from_slot(slot: S, probe: P) -> Self {
+        Self::from_parts(slot, State::NotReady, probe)
+    }
+}
+
+impl<S: WakerSlot, P: ChurnProbe, M: RawMutex, T> SingleSlotSignal<S, P, M, T> {
+    /// Create a signal from an empty slot and the initial value of the payload.
+    pub const fn from_parts(slot: S, initial: T, probe: P) -> Self {
+        Self {
+            inner: Mutex::new(Inner {
+                state: RefCell::new(initial),
+                waker_registration: RefCell::new(slot),
+                registered: Cell::new(None),
+                registration: Cell::new(0),
+                waits: Cell::new(0),
+                closed: Cell::new(false),
+            }),
+            probe,
+        }
+    }
+
+    /// Access the slot, e.g. to read the events of a [`LostWakeupDetector`](crate::lost_wakeup::LostWakeupDetector).
+    pub fn slot<R>(&self, f: impl FnOnce(&S) -> R) -> R {
+        self.inner.lock(|s| f(&s.waker_registration.borrow()))
+    }
+}
+
+impl<M: RawMutex> NaiveSignal<(), M> {
+    pub const fn new() -> Self {
+        Self::from_slot(None, ())
+    }
+}
+
+impl<P: ChurnProbe, M: RawMutex> NaiveSignal<P, M> {
+    /// Create a signal which reports its churn to `probe`.
+    pub const fn with_probe(probe: P) -> Self {
+        Self::from_slot(None, probe)
+    }
+}
+
+impl<P: ChurnProbe, M: RawMutex, T> NaiveSignal<P, M, T> {
+    /// Create a signal with a custom payload, starting at `initial`.
+    pub const fn with_initial(initial: T, probe: P) -> Self {
+        Self::from_parts(None, initial, probe)
+    }
+}
+
+impl<M: RawMutex, T: Default> Default for NaiveSignal<(), M, T> {
+    fn default() -> Self {
+        Self::with_initial(T::default(), ())
+    }
+}
+
+impl<M: RawMutex> AtomicWakerSignal<(), M> {
+    pub const fn new() -> Self {
+        Self::from_slot(AtomicWaker::new(), ())
+    }
+}
+
+impl<P: ChurnProbe, M: RawMutex> AtomicWakerSignal<P, M> {
+    /// Create a signal which reports its churn to `probe`.
+    pub const fn with_probe(probe: P) -> Self {
+        Self::from_slot(AtomicWaker::new(), probe)
+    }
+}
+
+impl<P: ChurnProbe, M: RawMutex, T> AtomicWakerSignal<P, M, T> {
+    /// Create a signal with a custom payload, starting at `initial`.
+    pub const fn with_initial(initial: T, probe: P) -> Self {
+        Self::from_parts(AtomicWaker::new(), initial, probe)
+    }
+}
+
+impl<M: RawMutex, T: Default> Default for AtomicWakerSignal<(), M, T> {
+    fn default() -> Self {
+        Self::with_initial(T::default(), ())
+    }
+}
+
+impl<M: RawMutex> WakerRegistrationSignal<(), M> {
+    pub const fn new() -> Self {
+        Self::from_slot(WakerRegistration::new(), ())
+    }
+}
+
+impl<P: ChurnProbe, M: RawMutex> WakerRegistrationSignal<P, M> {
+    /// Create a signal which reports its churn to `probe`.
+    pub const fn with_probe(probe: P) -> Self {
+        Self::from_slot(WakerRegistration::new(), probe)
+    }
+}
+
+impl<P: ChurnProbe, M: RawMutex, T> WakerRegistrationSignal<P, M, T> {
+    /// Create a signal with a custom payload, starting at `initial`.
+    pub const fn with_initial(initial: T, probe: P) -> Self {
+        Self::from_parts(WakerRegistration::new(), initial, probe)
+    }
+}
+
+impl<M: RawMutex, T: Default> Default for WakerRegistrationSignal<(), M, T> {
+    fn default() -> Self {
+        Self::with_initial(T::default(), ())
+    }
+}
+
+impl<S: WakerSlot, P: ChurnProbe, M: RawMutex, T: Clone + PartialEq> ChangeSignal<T>
+    for SingleSlotSignal<S, P, M, T>
+{
+    fn with<R>(&self, f: impl FnOnce(&T) -> R) -> R {
+        self.inner.lock(|s| f(&s.state.borrow()))
+    }
+
+    fn set(&self, value: T) {
+        self.inner.lock(|s| {
+            *s.state.borrow_mut() = value;
+            s.wake(&self.probe);
+        });
+    }
+
+    /// Only the stored waker is woken. A task whose waker was replaced without a wake (naive, `AtomicWaker`)
+    /// does not notice the close until it is polled for another reason.
+    fn close(&self) {
+        self.inner.lock(|s| {
+            s.closed.set(true);
+            s.wake(&self.probe);
+        });
+    }
+
+    fn is_closed(&self) -> bool {
+        self.inner.lock(|s| s.closed.get())
+    }
+
+    async fn wait_changed(&self, current: &T) -> Result<T, Closed> {
+        let mut counter = 0;
+        // The registration this wait holds, so a dropped wait can remove the waker again
+        let registered = &Cell::new(None);
+        let _unregister = OnDrop::new(|| {
+            if let Some(registration) = registered.get() {
+                self.inner.lock(|s| s.release(registration, true));
+            }
+        });
+
+        poll_fn(move |cx| {
+            let task = WakerId::of(cx.waker());
+            trace!("Running waker of task: {}", task_name(task));
+
+            self.inner.lock(|s| {
+                if s.closed.get() {
+                    if let Some(registration) = registered.take() {
+                        s.release(registration, false);
+                    }
+                    return Poll::Ready(Err(Closed));
+                }
+
+                let state = s.state.borrow();
+                if *state != *current {
+                    if let Some(registration) = registered.take() {
+                        s.release(registration, false);
+                    }
+                    info!(
+                        "{}: Signal is ready. Number of polls: {}",
+                        task_name(task),
+                        counter
+                    );
+                    self.probe.completed(task);
+                    Poll::Ready(Ok(state.clone()))
+                } else {
+                    trace!("{}: Signal not ready, registering waker", task_name(task));
+                    if counter > 0 {
+                        self.probe.spurious_poll(task);
+                    }
+                    counter += 1;
+                    self.probe.registered(task);
+
+                    let registration = s.register(registered.get(), task, cx.waker(), &self.probe);
+                    registered.set(Some(registration));
+                    Poll::Pending
+                }
+            })
+        })
+        .await
+    }
+}
@@ -0,0 +1,232 @@
+use core::cell::RefCell;
+
+use embassy_sync::blocking_mutex::Mutex;
+use embassy_sync::blocking_mutex::raw::{RawMutex, ThreadModeRawMutex};
+use maitake_sync::WaitQueue;
+
+use crate::names::register_current_task;
+use crate::{ChangeSignal, Closed, State};
+
+/// Result of [`Watcher::changed`].
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Changed<T = State> {
+    /// The latest state
+    pub state: T,
+    /// Number of updates which were published since the last call, but replaced before the watcher saw them
+    pub skipped: u32,
+}
+
+/// Signal which counts every `set` as a new generation, even if the state did not change.
+///
+/// Waiters which compare the state with `!=` can't know how many updates they missed. A [`Watcher`] remembers
+/// the last generation it saw, so [`Watcher::changed`] also returns the number of skipped updates.
+/// Like [`WaitQueueSignal`](crate::WaitQueueSignal) the waiters are stored in a `maitake_sync::WaitQueue`.
+pub struct VersionedSignal<M = ThreadModeRawMutex, T = State> {
+    /// Generation and state of the latest `set`
+    inner: Mutex<M, RefCell<(u32, T)>>,
+    waker_registration: WaitQueue,
+}
+
+impl<M: RawMutex> VersionedSignal<M> {
+    pub const fn new() -> Self {
+        Self::with_initial(State::NotReady)
+    }
+}
+
+impl<M: RawMutex, T> VersionedSignal<M, T> {
+    /// Create a signal with a custom payload, starting at `initial`.
+    pub const fn with_initial(initial: T) -> Self {
+        Self {
+            inner: Mutex::new(RefCell::new((0, initial))),
+            waker_registration: WaitQueue::new(),
+        }
+    }
+
+    /// Number of `set` calls so far (wrapping).
+    pub fn generation(&self) -> u32 {
+        self.inner.lock(|s| s.borrow().0)
+    }
+
+    /// Create a watcher which has seen the current generation.
+    pub fn watcher(&self) -> Watcher<'_, M, T> {
+        Watcher {
+            signal: self,
+            seen: self.generation(),
+        }
+    }
+}
+
+impl<M: RawMutex, T: Default> Default for VersionedSignal<M, T> {
+    fn default() -> Self {
+        Self::with_initial(T::default())
+    }
+}
+
+impl<M: RawMutex, T: Clone + PartialEq> ChangeSignal<T> for VersionedSignal<M, T> {
+    fn with<R>(&self, f: impl FnOnce(&T) -> R) -> R {
+        self.inner.lock(|s| f(&s.borrow().1))
+    }
+
+    fn set(&self, value: T) {
+        self.inner.lock(|s| {
+            let mut inner = s.borrow_mut();
+            inner.0 = inner.0.wrapping_add(1);
+            inner.1 = value;
+        });
+        self.waker_registration.wake_all();
+    }
+
+    fn close(&self) {
+        self.waker_registration.close();
+    }
+
+    fn is_closed(&self) -> bool {
+        self.waker_registration.is_closed()
+    }
+
+    /// The state is returned from the same lock which found it changed, so it never equals `current`.
+    async fn wait_changed(&self, current: &T) -> Result<T, Closed> {
+        self.waker_registration
+            .wait_for_value(|| self.with(|state| (state != current).then(|| state.clone())))
+            .await
+            .map_err(|_| Closed)
+    }
+}
+
+/// Waits for new generations of a [`VersionedSignal`].
+pub struct Watcher<'a, M: RawMutex = ThreadModeRawMutex, T = State> {
+    signal: &'a VersionedSignal<M, T>,
+    /// The last generation returned by `changed`
+    seen: u32,
+}
+
+impl<M: RawMutex, T: Clone> Watcher<'_, M, T> {
+    /// The last generation this watcher has seen.
+    pub fn seen(&self) -> u32 {
+        self.seen
+    }
+
+    /// Wait for a generation newer than the last one seen and return its state and the number of skipped generations.
+    pub async fn changed(&mut self) -> Result<Changed<T>, Closed> {
+        let signal = self.signal;
+        let seen = self.seen;
+
+        signal
+            .waker_registration
+            .wait_for(|| signal.generation() != seen)
+            .await
+            .map_err(|_| Closed)?;
+
+        let (generation, state) = signal.inner.lock(|s| s.borrow().clone());
+        self.seen = generation;
+
+        Ok(Changed {
+            state,
+            skipped: generation.wrapping_sub(seen) - 1,
+        })
+    }
+}
+
+/// Task body like [`wait_for_signal`](crate::wait_for_signal), but it also reports the updates it missed.
+///
+/// So the task can tell "no odd/even state was published" apart from "it was published, but replaced before the task ran".
+pub async fn watch_signal<M: RawMutex>(name: &'static str, signal: &VersionedSignal<M>, odd: bool) {
+    info!("Starting {} task", name);
+    let _name = register_current_task(name).await;
+
+    let mut watcher = signal.watcher();
+
+    loop {
+        let Ok(Changed { state, skipped }) = watcher.changed().await else {
+            info!("{}: Signal closed", name);
+            return;
+        };
+
+        if skipped > 0 {
+            warn!("{}: Missed {} updates before {:?}", name, skipped, state);
+        }
+
+        match (odd, state) {
+            (true, State::Ready(x)) if x % 2 == 1 => {
+                info!("{}: Odd state: {:?}", name, state);
+            }
+            (false, State::Ready(x)) if x % 2 == 0 => {
+                info!("{}: Even state: {:?}", name, state);
+            }
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use core::pin::pin;
+    use core::task::{Context, Poll};
+
+    use super::*;
+    use crate::test_util::{CountingWaker, in_thread_mode};
+
+    #[test]
+    fn test_skipped_generations() {
+        in_thread_mode(|| {
+            let signal: VersionedSignal = VersionedSignal::new();
+            let mut watcher = signal.watcher();
+            let (counter, waker) = CountingWaker::new();
+            let mut cx = Context::from_waker(&waker);
+
+            {
+                let mut changed = pin!(watcher.changed());
+                assert_eq!(changed.as_mut().poll(&mut cx), Poll::Pending);
+
+                signal.set(State::Ready(1));
+                assert_eq!(counter.wakes(), 1);
+                assert_eq!(
+                    changed.as_mut().poll(&mut cx),
+                    Poll::Ready(Ok(Changed {
+                        state: State::Ready(1),
+                        skipped: 0
+                    }))
+                );
+            }
+
+            // The watcher is slow, so two updates are replaced before it runs
+            for x in 2..=4 {
+                signal.set(State::Ready(x));
+            }
+            {
+                let changed = pin!(watcher.changed());
+                assert_eq!(
+                    changed.poll(&mut cx),
+                    Poll::Ready(Ok(Changed {
+                        state: State::Ready(4),
+                        skipped: 2
+                    }))
+                );
+            }
+            assert_eq!(watcher.seen(), 4);
+        });
+    }
+
+    #[test]
+    fn test_same_state_is_new_generation() {
+        in_thread_mode(|| {
+            let signal: VersionedSignal = VersionedSignal::new();
+            signal.set(State::Ready(2));
+            let mut watcher = signal.watcher();
+
+            signal.set(State::Ready(2));
+
+            let (_, waker) = CountingWaker::new();
+            let mut cx = Context::from_waker(&waker);
+            let changed = pin!(watcher.changed());
+            assert_eq!(
+                changed.poll(&mut cx),
+                Poll::Ready(Ok(Changed {
+                    state: State::Ready(2),
+                    skipped: 0
+                }))
+            );
+        });
+    }
+}
@@ -0,0 +1,127 @@
+use core::cell::{Cell, RefCell};
+
+use embassy_sync::blocking_mutex::Mutex;
+use embassy_sync::blocking_mutex::raw::{RawMutex, ThreadModeRawMutex};
+use maitake_sync::WaitQueue;
+
+use crate::stats::ChurnProbe;
+use crate::{ChangeSignal, Closed, HandOffSignal, State, WakerId};
+
+/// Uses the intrusive wait queue of `maitake_sync`, so all waiters are woken up and no manual "leaf" future is needed.
+/// Also the number of waiters does not need to be specified upfront.
+/// `M` protects the state, see [`SingleSlotSignal`](crate::SingleSlotSignal). The wait queue has its own lock, which takes a critical section.
+/// `T` is the payload, see [`ChangeSignal`].
+pub struct WaitQueueSignal<P = (), M = ThreadModeRawMutex, T = State> {
+    state: Mutex<M, RefCell<T>>,
+    /// Update of `notify_one` which no waiter took yet
+    handed: Mutex<M, Cell<Option<T>>>,
+    waker_registration: WaitQueue,
+    probe: P,
+}
+
+impl<M: RawMutex> WaitQueueSignal<(), M> {
+    pub const fn new() -> Self {
+        Self::with_probe(())
+    }
+}
+
+impl<P: ChurnProbe, M: RawMutex> WaitQueueSignal<P, M> {
+    /// Create a signal which reports its churn to `probe`.
+    pub const fn with_probe(probe: P) -> Self {
+        Self::with_initial(State::NotReady, probe)
+    }
+}
+
+impl<P: ChurnProbe, M: RawMutex, T> WaitQueueSignal<P, M, T> {
+    /// Create a signal with a custom payload, starting at `initial`.
+    pub const fn with_initial(initial: T, probe: P) -> Self {
+        Self {
+            state: Mutex::new(RefCell::new(initial)),
+            handed: Mutex::new(Cell::new(None)),
+            waker_registration: WaitQueue::new(),
+            probe,
+        }
+    }
+}
+
+impl<M: RawMutex, T: Default> Default for WaitQueueSignal<(), M, T> {
+    fn default() -> Self {
+        Self::with_initial(T::default(), ())
+    }
+}
+
+impl<P: ChurnProbe, M: RawMutex, T: Clone + PartialEq> ChangeSignal<T>
+    for WaitQueueSignal<P, M, T>
+{
+    fn with<R>(&self, f: impl FnOnce(&T) -> R) -> R {
+        self.state.lock(|s| f(&s.borrow()))
+    }
+
+    fn set(&self, value: T) {
+        self.state.lock(|s| {
+            *s.borrow_mut() = value;
+        });
+        self.waker_registration.wake_all();
+    }
+
+    fn close(&self) {
+        self.waker_registration.close();
+    }
+
+    fn is_closed(&self) -> bool {
+        self.waker_registration.is_closed()
+    }
+
+    async fn wait_changed(&self, current: &T) -> Result<T, Closed> {
+        let task = WakerId::current().await;
+        let mut first_check = true;
+
+        // `wait_for_value` checks the condition once up front and again after every wake of the queue.
+        // The state is returned from the same lock which found it changed.
+        self.waker_registration
+            .wait_for_value(|| {
+                let first = core::mem::replace(&mut first_check, false);
+                if !first {
+                    self.probe.woken(task);
+                }
+
+                let changed = self.with(|state| (state != current).then(|| state.clone()));
+                if changed.is_some() {
+                    self.probe.completed(task);
+                } else {
+                    if !first {
+                        self.probe.spurious_poll(task);
+                    }
+                    self.probe.registered(task);
+                }
+                changed
+            })
+            .await
+            .map_err(|_| Closed)
+    }
+}
+
+/// `WaitQueue::wake` wakes the task which waits the longest. If nobody waits, the wakeup is stored for the next waiter.
+impl<P: ChurnProbe, M: RawMutex, T: Clone + PartialEq> HandOffSignal<T>
+    for WaitQueueSignal<P, M, T>
+{
+    fn notify_one(&self, value: T) -> Option<T> {
+        self.state.lock(|s| *s.borrow_mut() = value.clone());
+        let replaced = self.handed.lock(|s| s.replace(Some(value)));
+        self.waker_registration.wake();
+        replaced
+    }
+
+    async fn wait_handed(&self) -> Result<T, Closed> {
+        let task = WakerId::current().await;
+
+        let state = self
+            .waker_registration
+            .wait_for_value(|| self.handed.lock(|s| s.take()))
+            .await
+            .map_err(|_| Closed)?;
+
+        self.probe.completed(task);
+        Ok(state)
+    }
+}
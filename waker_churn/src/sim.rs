@@ -0,0 +1,434 @@
+//! Deterministic host-side simulator of the embassy executor.
+//!
+//! The futures of the demos (e.g. [`wait_for_signal`](crate::wait_for_signal)) are polled in the same order
+//! as `embassy_executor` polls its run queue: tasks are dequeued in batches, and a batch is polled in the
+//! reverse order of the wakes which enqueued its tasks. A task which is already queued is not enqueued twice.
+//! Every poll advances a virtual clock, so a producer can publish changes at fixed points in (virtual) time
+//! and a run can be replayed exactly, including the ping-pong of `WakerRegistration`.
+//!
+//! ```
+//! use waker_churn::sim::{RunOrder, Simulator};
+//! use waker_churn::{ChangeSignal, MultiWakerRegistrationSignal, State};
+//!
+//! # std::thread::Builder::new().name("main".into()).spawn(|| {
+//! let signal: MultiWakerRegistrationSignal<2> = MultiWakerRegistrationSignal::new();
+//! let mut sim = Simulator::new(RunOrder::Embassy);
+//! let task_two = sim.spawn("TaskTwo", waker_churn::wait_for_signal("TaskTwo", &signal, true));
+//! let task_one = sim.spawn("TaskOne", waker_churn::wait_for_signal("TaskOne", &signal, false));
+//!
+//! sim.run_for(500);
+//! signal.set(State::Ready(1));
+//! sim.run_for(500);
+//!
+//! assert_eq!(sim.task(task_two).polls, 2);
+//! assert_eq!(sim.task(task_one).polls, 2);
+//! # }).unwrap().join().unwrap();
+//! ```
+
+use std::boxed::Box;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Wake, Waker};
+use std::vec::Vec;
+
+/// Index of a task spawned on the [`Simulator`].
+pub type TaskId = usize;
+
+/// Order in which the tasks of a batch are polled.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RunOrder {
+    /// Like the run queue of `embassy_executor`: the task woken last is polled first.
+    Embassy,
+    /// The task woken first is polled first.
+    Fifo,
+    /// Every batch is shuffled with a PRNG using the given seed, to explore other interleavings.
+    Shuffled(u64),
+}
+
+/// Something which happened during the simulation.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SimEvent {
+    /// The task was spawned and enqueued.
+    Spawn { task: TaskId, at: u64 },
+    /// The waker of the task was called. `enqueued` is false if the task was already in the run queue.
+    Wake {
+        task: TaskId,
+        at: u64,
+        enqueued: bool,
+    },
+    /// The task was polled. `ready` is true if its future completed.
+    Poll { task: TaskId, at: u64, ready: bool },
+}
+
+/// Counters of a single task.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TaskStats {
+    /// Name the task was spawned with.
+    pub name: &'static str,
+    /// Number of polls.
+    pub polls: u64,
+    /// Number of wakes, including the ones of a task which was already queued.
+    pub wakes: u64,
+    /// True if the future of the task completed.
+    pub completed: bool,
+}
+
+/// State shared between the simulator and the wakers of its tasks.
+struct Scheduler {
+    /// Virtual time in ticks
+    now: u64,
+    /// Run queue in the order of the wakes
+    queue: Vec<TaskId>,
+    /// Marks the tasks which are currently in the run queue
+    queued: Vec<bool>,
+    /// Number of wakes per task
+    wakes: Vec<u64>,
+    events: Vec<SimEvent>,
+}
+
+/// Waker of a simulated task. All clones share the same data pointer, so `Waker::will_wake` behaves like on embassy.
+struct TaskWaker {
+    task: TaskId,
+    scheduler: Arc<Mutex<Scheduler>>,
+}
+
+impl Wake for TaskWaker {
+    fn wake(self: Arc<Self>) {
+        self.wake_by_ref();
+    }
+
+    fn wake_by_ref(self: &Arc<Self>) {
+        let mut scheduler = self.scheduler.lock().unwrap();
+        let enqueued = !scheduler.queued[self.task];
+
+        if enqueued {
+            scheduler.queued[self.task] = true;
+            scheduler.queue.push(self.task);
+        }
+
+        scheduler.wakes[self.task] += 1;
+        let at = scheduler.now;
+        scheduler.events.push(SimEvent::Wake {
+            task: self.task,
+            at,
+            enqueued,
+        });
+    }
+}
+
+/// Reads the virtual time of a [`Simulator`], e.g. from inside one of its tasks.
+#[derive(Clone)]
+pub struct Clock {
+    scheduler: Arc<Mutex<Scheduler>>,
+}
+
+impl Clock {
+    /// Current virtual time in ticks.
+    pub fn now(&self) -> u64 {
+        self.scheduler.lock().unwrap().now
+    }
+}
+
+struct SimTask<'a> {
+    name: &'static str,
+    /// `None` once the future completed
+    future: Option<Pin<Box<dyn Future<Output = ()> + 'a>>>,
+    waker: Waker,
+    polls: u64,
+}
+
+/// Single threaded executor which polls its tasks in a reproducible order.
+pub struct Simulator<'a> {
+    order: RunOrder,
+    /// Ticks the virtual clock advances per poll
+    poll_cost: u64,
+    rng: u64,
+    tasks: Vec<SimTask<'a>>,
+    scheduler: Arc<Mutex<Scheduler>>,
+}
+
+impl<'a> Simulator<'a> {
+    /// Create a simulator where every poll takes one tick.
+    pub fn new(order: RunOrder) -> Self {
+        Self::with_poll_cost(order, 1)
+    }
+
+    /// Create a simulator where every poll takes `poll_cost` ticks.
+    pub fn with_poll_cost(order: RunOrder, poll_cost: u64) -> Self {
+        let rng = match order {
+            // xorshift must not be seeded with 0
+            RunOrder::Shuffled(seed) => seed | 1,
+            _ => 1,
+        };
+
+        Self {
+            order,
+            poll_cost,
+            rng,
+            tasks: Vec::new(),
+            scheduler: Arc::new(Mutex::new(Scheduler {
+                now: 0,
+                queue: Vec::new(),
+                queued: Vec::new(),
+                wakes: Vec::new(),
+                events: Vec::new(),
+            })),
+        }
+    }
+
+    /// Spawn a task. Like on embassy, the task is enqueued and polled with the next batch.
+    pub fn spawn(&mut self, name: &'static str, future: impl Future<Output = ()> + 'a) -> TaskId {
+        let task = self.tasks.len();
+        let waker = Waker::from(Arc::new(TaskWaker {
+            task,
+            scheduler: self.scheduler.clone(),
+        }));
+
+        self.tasks.push(SimTask {
+            name,
+            future: Some(Box::pin(future)),
+            waker,
+            polls: 0,
+        });
+
+        let mut scheduler = self.scheduler.lock().unwrap();
+        scheduler.queued.push(true);
+        scheduler.queue.push(task);
+        scheduler.wakes.push(0);
+        let at = scheduler.now;
+        scheduler.events.push(SimEvent::Spawn { task, at });
+
+        task
+    }
+
+    /// Current virtual time in ticks.
+    pub fn now(&self) -> u64 {
+        self.scheduler.lock().unwrap().now
+    }
+
+    /// Handle to the virtual clock which can be moved into a task.
+    pub fn clock(&self) -> Clock {
+        Clock {
+            scheduler: self.scheduler.clone(),
+        }
+    }
+
+    /// True if no task is in the run queue.
+    pub fn is_idle(&self) -> bool {
+        self.scheduler.lock().unwrap().queue.is_empty()
+    }
+
+    /// Poll all tasks which are currently in the run queue. Tasks woken meanwhile are left for the next batch.
+    /// Returns the number of polls.
+    pub fn run_batch(&mut self) -> usize {
+        let batch = {
+            let mut scheduler = self.scheduler.lock().unwrap();
+            core::mem::take(&mut scheduler.queue)
+        };
+
+        let batch = self.order_batch(batch);
+        let polls = batch.len();
+
+        for task in batch {
+            self.poll_task(task);
+        }
+
+        polls
+    }
+
+    /// Run batches until the run queue is empty or `max_polls` polls are done.
+    /// Returns false if the tasks are still busy, e.g. because two of them keep waking each other.
+    pub fn run_until_idle(&mut self, max_polls: usize) -> bool {
+        let mut polls = 0;
+
+        while !self.is_idle() {
+            if polls >= max_polls {
+                return false;
+            }
+            polls += self.run_batch();
+        }
+
+        true
+    }
+
+    /// Run batches for `ticks` of virtual time. If all tasks are idle, the clock jumps to the end of the period.
+    /// The batch which crosses the end of the period is completed, like on a real executor.
+    pub fn run_for(&mut self, ticks: u64) {
+        let deadline = self.now() + ticks;
+
+        while self.now() < deadline {
+            if self.is_idle() {
+                self.scheduler.lock().unwrap().now = deadline;
+                break;
+            }
+            self.run_batch();
+        }
+    }
+
+    /// Counters of a task.
+    pub fn task(&self, task: TaskId) -> TaskStats {
+        TaskStats {
+            name: self.tasks[task].name,
+            polls: self.tasks[task].polls,
+            wakes: self.scheduler.lock().unwrap().wakes[task],
+            completed: self.tasks[task].future.is_none(),
+        }
+    }
+
+    /// Counters of all tasks, indexed by [`TaskId`].
+    pub fn tasks(&self) -> Vec<TaskStats> {
+        (0..self.tasks.len()).map(|task| self.task(task)).collect()
+    }
+
+    /// Total number of polls of all tasks.
+    pub fn total_polls(&self) -> u64 {
+        self.tasks.iter().map(|task| task.polls).sum()
+    }
+
+    /// Total number of wakes of all tasks.
+    pub fn total_wakes(&self) -> u64 {
+        self.scheduler.lock().unwrap().wakes.iter().sum()
+    }
+
+    /// Everything which happened so far, in order.
+    pub fn events(&self) -> Vec<SimEvent> {
+        self.scheduler.lock().unwrap().events.clone()
+    }
+
+    fn order_batch(&mut self, mut batch: Vec<TaskId>) -> Vec<TaskId> {
+        match self.order {
+            RunOrder::Embassy => batch.reverse(),
+            RunOrder::Fifo => {}
+            RunOrder::Shuffled(_) => {
+                // Fisher-Yates
+                for i in (1..batch.len()).rev() {
+                    let j = (self.next_random() % (i as u64 + 1)) as usize;
+                    batch.swap(i, j);
+                }
+            }
+        }
+
+        batch
+    }
+
+    /// xorshift64*
+    fn next_random(&mut self) -> u64 {
+        self.rng ^= self.rng >> 12;
+        self.rng ^= self.rng << 25;
+        self.rng ^= self.rng >> 27;
+        self.rng.wrapping_mul(0x2545_F491_4F6C_DD1D)
+    }
+
+    fn poll_task(&mut self, task: TaskId) {
+        let at = {
+            let mut scheduler = self.scheduler.lock().unwrap();
+            // Like `run_dequeue` on embassy: the task can be enqueued again while it is polled
+            scheduler.queued[task] = false;
+            scheduler.now += self.poll_cost;
+            scheduler.now
+        };
+
+        let sim_task = &mut self.tasks[task];
+        let Some(future) = sim_task.future.as_mut() else {
+            // Woken after it completed, embassy ignores this as well
+            return;
+        };
+
+        sim_task.polls += 1;
+        let mut cx = Context::from_waker(&sim_task.waker);
+        let ready = future.as_mut().poll(&mut cx).is_ready();
+
+        if ready {
+            sim_task.future = None;
+        }
+
+        self.scheduler
+            .lock()
+            .unwrap()
+            .events
+            .push(SimEvent::Poll { task, at, ready });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::in_thread_mode;
+    use crate::{ChangeSignal, State, WakerRegistrationSignal, wait_for_signal};
+
+    /// Run the `embassy_waker_registration` demo for a few updates.
+    fn run_waker_registration(order: RunOrder) -> (Vec<TaskStats>, Vec<SimEvent>) {
+        let signal: WakerRegistrationSignal = WakerRegistrationSignal::new();
+        let mut sim = Simulator::new(order);
+        sim.spawn("TaskTwo", wait_for_signal("TaskTwo", &signal, true));
+        sim.spawn("TaskOne", wait_for_signal("TaskOne", &signal, false));
+
+        for counter in 1..=4 {
+            sim.run_for(50);
+            signal.set(State::Ready(counter));
+        }
+        sim.run_for(50);
+
+        (sim.tasks(), sim.events())
+    }
+
+    #[test]
+    fn test_embassy_order() {
+        in_thread_mode(|| {
+            let done = core::cell::RefCell::new(Vec::new());
+            let mut sim = Simulator::new(RunOrder::Embassy);
+
+            for name in ["A", "B", "C"] {
+                let done = &done;
+                sim.spawn(name, async move { done.borrow_mut().push(name) });
+            }
+            assert!(sim.run_until_idle(10));
+
+            // Spawned in order A, B, C -> polled in reverse
+            assert_eq!(*done.borrow(), ["C", "B", "A"]);
+        });
+    }
+
+    #[test]
+    fn test_waker_registration_ping_pong() {
+        in_thread_mode(|| {
+            let (tasks, events) = run_waker_registration(RunOrder::Embassy);
+
+            // Both tasks keep waking each other. So they are polled on every tick, not only after a change.
+            assert_eq!(tasks[0].polls + tasks[1].polls, 250);
+            assert!(tasks[0].wakes >= 100);
+            assert!(tasks[1].wakes >= 100);
+
+            // ...and the run is reproducible
+            assert_eq!(run_waker_registration(RunOrder::Embassy).1, events);
+        });
+    }
+
+    #[test]
+    fn test_shuffled_is_reproducible() {
+        in_thread_mode(|| {
+            let (_, first) = run_waker_registration(RunOrder::Shuffled(42));
+            let (_, second) = run_waker_registration(RunOrder::Shuffled(42));
+            assert_eq!(first, second);
+        });
+    }
+
+    #[test]
+    fn test_no_polls_while_idle() {
+        in_thread_mode(|| {
+            let signal: crate::WaitQueueSignal = crate::WaitQueueSignal::new();
+            let mut sim = Simulator::new(RunOrder::Embassy);
+            let task = sim.spawn("TaskOne", wait_for_signal("TaskOne", &signal, false));
+
+            sim.run_for(1000);
+            assert_eq!(sim.task(task).polls, 1);
+            assert_eq!(sim.now(), 1000);
+
+            signal.set(State::Ready(2));
+            sim.run_for(1000);
+            assert_eq!(sim.task(task).polls, 2);
+            assert_eq!(sim.task(task).wakes, 1);
+        });
+    }
+}
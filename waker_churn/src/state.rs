@@ -0,0 +1,8 @@
+/// The value which is published by the producer and waited on by the tasks.
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum State {
+    #[default]
+    NotReady,
+    Ready(u32),
+}
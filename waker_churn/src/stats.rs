@@ -0,0 +1,307 @@
+//! Instrumentation of the waker strategies.
+//!
+//! Every strategy accepts a [`ChurnProbe`] which is told about each registration, replacement, wake,
+//! spurious poll and completed wait. [`ChurnStats`] is a probe which counts these events per task.
+
+use core::cell::RefCell;
+
+use embassy_sync::blocking_mutex::Mutex;
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use heapless::Vec;
+
+use crate::WakerId;
+use crate::names::task_name;
+
+/// Hooks which are called by the strategies. All of them do nothing by default.
+pub trait ChurnProbe {
+    /// The task registered its waker because the state did not change yet.
+    fn registered(&self, _task: WakerId) {}
+
+    /// The stored waker of the task was overwritten or evicted by another task.
+    fn replaced(&self, _task: WakerId) {}
+
+    /// The strategy woke the waker of the task.
+    fn woken(&self, _task: WakerId) {}
+
+    /// The task was polled again but the state did still not change.
+    fn spurious_poll(&self, _task: WakerId) {}
+
+    /// A wait of the task completed because the state changed.
+    fn completed(&self, _task: WakerId) {}
+}
+
+/// No instrumentation at all. This is the default probe of every strategy.
+impl ChurnProbe for () {}
+
+impl<P: ChurnProbe + ?Sized> ChurnProbe for &P {
+    fn registered(&self, task: WakerId) {
+        (**self).registered(task)
+    }
+
+    fn replaced(&self, task: WakerId) {
+        (**self).replaced(task)
+    }
+
+    fn woken(&self, task: WakerId) {
+        (**self).woken(task)
+    }
+
+    fn spurious_poll(&self, task: WakerId) {
+        (**self).spurious_poll(task)
+    }
+
+    fn completed(&self, task: WakerId) {
+        (**self).completed(task)
+    }
+}
+
+/// Counters of a single task.
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TaskChurn {
+    pub task: WakerId,
+    /// Number of times the task registered its waker
+    pub registrations: u32,
+    /// Number of times the registration of the task was overwritten by another task
+    pub replacements: u32,
+    /// Number of wakes issued to the task
+    pub wakes: u32,
+    /// Number of polls which did not complete the wait
+    pub spurious_polls: u32,
+    /// Number of waits which completed
+    pub completed_waits: u32,
+}
+
+impl TaskChurn {
+    fn new(task: WakerId) -> Self {
+        Self {
+            task,
+            registrations: 0,
+            replacements: 0,
+            wakes: 0,
+            spurious_polls: 0,
+            completed_waits: 0,
+        }
+    }
+}
+
+/// Records the churn of up to `N` tasks. Events of further tasks are only counted in [`ChurnStats::untracked`].
+pub struct ChurnStats<const N: usize> {
+    inner: Mutex<CriticalSectionRawMutex, RefCell<Inner<N>>>,
+}
+
+struct Inner<const N: usize> {
+    tasks: Vec<TaskChurn, N>,
+    untracked: u32,
+}
+
+impl<const N: usize> ChurnStats<N> {
+    pub const fn new() -> Self {
+        Self {
+            inner: Mutex::new(RefCell::new(Inner {
+                tasks: Vec::new(),
+                untracked: 0,
+            })),
+        }
+    }
+
+    /// Counters of a single task, if it was recorded.
+    pub fn task(&self, task: WakerId) -> Option<TaskChurn> {
+        self.inner.lock(|inner| {
+            let inner = inner.borrow();
+            inner.tasks.iter().find(|t| t.task == task).copied()
+        })
+    }
+
+    /// Counters of all recorded tasks, in the order they were first seen.
+    pub fn summary(&self) -> Vec<TaskChurn, N> {
+        self.inner.lock(|inner| inner.borrow().tasks.clone())
+    }
+
+    /// Number of events which were dropped because more than `N` tasks were recorded.
+    pub fn untracked(&self) -> u32 {
+        self.inner.lock(|inner| inner.borrow().untracked)
+    }
+
+    /// Log the counters of all recorded tasks.
+    pub fn log_summary(&self) {
+        for t in self.summary() {
+            info!(
+                "{} ({}): registrations: {}, replacements: {}, wakes: {}, spurious polls: {}, completed waits: {}",
+                task_name(t.task),
+                t.task,
+                t.registrations,
+                t.replacements,
+                t.wakes,
+                t.spurious_polls,
+                t.completed_waits
+            );
+        }
+
+        let untracked = self.untracked();
+        if untracked > 0 {
+            warn!("{} events of untracked tasks", untracked);
+        }
+    }
+
+    /// Forget all recorded tasks.
+    pub fn reset(&self) {
+        self.inner.lock(|inner| {
+            let mut inner = inner.borrow_mut();
+            inner.tasks.clear();
+            inner.untracked = 0;
+        })
+    }
+
+    fn update(&self, task: WakerId, f: impl FnOnce(&mut TaskChurn)) {
+        self.inner.lock(|inner| {
+            let mut inner = inner.borrow_mut();
+
+            if let Some(t) = inner.tasks.iter_mut().find(|t| t.task == task) {
+                f(t);
+                return;
+            }
+
+            let mut t = TaskChurn::new(task);
+            f(&mut t);
+            if inner.tasks.push(t).is_err() {
+                inner.untracked += 1;
+            }
+        })
+    }
+}
+
+impl<const N: usize> Default for ChurnStats<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const N: usize> ChurnProbe for ChurnStats<N> {
+    fn registered(&self, task: WakerId) {
+        self.update(task, |t| t.registrations += 1);
+    }
+
+    fn replaced(&self, task: WakerId) {
+        self.update(task, |t| t.replacements += 1);
+    }
+
+    fn woken(&self, task: WakerId) {
+        self.update(task, |t| t.wakes += 1);
+    }
+
+    fn spurious_poll(&self, task: WakerId) {
+        self.update(task, |t| t.spurious_polls += 1);
+    }
+
+    fn completed(&self, task: WakerId) {
+        self.update(task, |t| t.completed_waits += 1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sim::{RunOrder, Simulator};
+    use crate::test_util::in_thread_mode;
+    use crate::{
+        ChangeSignal, MultiWakerRegistrationSignal, NaiveSignal, State, WaitQueueSignal,
+        WakerRegistrationSignal, wait_for_signal,
+    };
+
+    const UPDATES: u32 = 3;
+
+    fn run<S: ChangeSignal>(signal: &S) {
+        let mut sim = Simulator::new(RunOrder::Embassy);
+        sim.spawn("TaskTwo", wait_for_signal("TaskTwo", signal, true));
+        sim.spawn("TaskOne", wait_for_signal("TaskOne", signal, false));
+
+        for counter in 1..=UPDATES {
+            sim.run_for(20);
+            signal.set(State::Ready(counter));
+        }
+        sim.run_for(20);
+    }
+
+    #[test]
+    fn test_multi_waker_registration() {
+        in_thread_mode(|| {
+            let stats = ChurnStats::<2>::new();
+            run(&MultiWakerRegistrationSignal::<2, _>::with_probe(&stats));
+
+            let summary = stats.summary();
+            assert_eq!(summary.len(), 2);
+            for t in summary {
+                assert_eq!(t.registrations, UPDATES + 1);
+                assert_eq!(t.replacements, 0);
+                assert_eq!(t.wakes, UPDATES);
+                assert_eq!(t.spurious_polls, 0);
+                assert_eq!(t.completed_waits, UPDATES);
+            }
+        });
+    }
+
+    #[test]
+    fn test_wait_queue() {
+        in_thread_mode(|| {
+            let stats = ChurnStats::<2>::new();
+            run(&WaitQueueSignal::<_>::with_probe(&stats));
+
+            for t in stats.summary() {
+                assert_eq!(t.registrations, UPDATES + 1);
+                assert_eq!(t.wakes, UPDATES);
+                assert_eq!(t.spurious_polls, 0);
+                assert_eq!(t.completed_waits, UPDATES);
+            }
+        });
+    }
+
+    #[test]
+    fn test_naive() {
+        in_thread_mode(|| {
+            let stats = ChurnStats::<2>::new();
+            run(&NaiveSignal::<_>::with_probe(&stats));
+
+            // TaskOne registers first and gets replaced by TaskTwo, which gets every wake
+            let summary = stats.summary();
+            let (task_one, task_two) = (summary[0], summary[1]);
+            assert_eq!(task_one.replacements, 1);
+            assert_eq!(task_one.wakes, 0);
+            assert_eq!(task_one.completed_waits, 0);
+            assert_eq!(task_two.replacements, 0);
+            assert_eq!(task_two.wakes, UPDATES);
+            assert_eq!(task_two.completed_waits, UPDATES);
+        });
+    }
+
+    #[test]
+    fn test_waker_registration() {
+        in_thread_mode(|| {
+            let stats = ChurnStats::<2>::new();
+            run(&WakerRegistrationSignal::<_>::with_probe(&stats));
+
+            for t in stats.summary() {
+                assert_eq!(t.completed_waits, UPDATES);
+                // The two tasks keep replacing and waking each other
+                assert!(t.replacements > 10 * UPDATES);
+                assert!(t.spurious_polls > 10 * UPDATES);
+                assert!(t.wakes >= t.replacements);
+            }
+        });
+    }
+
+    #[test]
+    fn test_untracked() {
+        let stats = ChurnStats::<1>::new();
+        let (_, first) = crate::test_util::CountingWaker::new();
+        let (_, second) = crate::test_util::CountingWaker::new();
+
+        stats.registered(WakerId::of(&first));
+        stats.registered(WakerId::of(&second));
+        stats.woken(WakerId::of(&first));
+
+        assert_eq!(stats.task(WakerId::of(&first)).unwrap().wakes, 1);
+        assert_eq!(stats.task(WakerId::of(&second)), None);
+        assert_eq!(stats.untracked(), 1);
+    }
+}
@@ -0,0 +1,57 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, PoisonError};
+use std::task::{Wake, Waker};
+
+use embassy_time::MockDriver;
+
+/// `ThreadModeMutex` only allows access from a thread named `main` when running on std.
+/// The test harness runs every test on its own thread, so hand the test body over to one.
+pub(crate) fn in_thread_mode<F: FnOnce() + Send>(f: F) {
+    std::thread::scope(|scope| {
+        std::thread::Builder::new()
+            .name("main".into())
+            .spawn_scoped(scope, f)
+            .expect("Failed to spawn thread")
+            .join()
+            .expect("Test panicked");
+    });
+}
+
+/// The mock time driver is global, so the tests which advance it take turns.
+/// Each one starts at time 0 with an empty timer queue.
+pub(crate) fn with_mock_time<R>(f: impl FnOnce(&MockDriver) -> R) -> R {
+    static LOCK: Mutex<()> = Mutex::new(());
+    let _guard = LOCK.lock().unwrap_or_else(PoisonError::into_inner);
+
+    let driver = MockDriver::get();
+    driver.reset();
+    f(driver)
+}
+
+/// Waker which counts how often it was woken.
+#[derive(Default)]
+pub(crate) struct CountingWaker {
+    wakes: AtomicUsize,
+}
+
+impl CountingWaker {
+    pub(crate) fn new() -> (Arc<Self>, Waker) {
+        let counter = Arc::new(Self::default());
+        let waker = Waker::from(counter.clone());
+        (counter, waker)
+    }
+
+    pub(crate) fn wakes(&self) -> usize {
+        self.wakes.load(Ordering::Relaxed)
+    }
+}
+
+impl Wake for CountingWaker {
+    fn wake(self: Arc<Self>) {
+        self.wake_by_ref();
+    }
+
+    fn wake_by_ref(self: &Arc<Self>) {
+        self.wakes.fetch_add(1, Ordering::Relaxed);
+    }
+}
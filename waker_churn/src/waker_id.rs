@@ -0,0 +1,39 @@
+use core::fmt;
+use core::future::poll_fn;
+use core::task::{Poll, Waker};
+
+/// Identity of a task, derived from the data pointer of its waker.
+///
+/// All wakers of an embassy task point to its task header, so the pointer identifies the task.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct WakerId(usize);
+
+impl WakerId {
+    /// Identity of the task the waker belongs to.
+    pub fn of(waker: &Waker) -> Self {
+        Self(waker.data() as usize)
+    }
+
+    /// Identity of the task which is currently polling.
+    pub async fn current() -> Self {
+        poll_fn(|cx| Poll::Ready(Self::of(cx.waker()))).await
+    }
+
+    /// The raw data pointer of the waker.
+    pub fn address(&self) -> usize {
+        self.0
+    }
+}
+
+impl fmt::Display for WakerId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:#x}", self.0)
+    }
+}
+
+#[cfg(feature = "defmt")]
+impl defmt::Format for WakerId {
+    fn format(&self, f: defmt::Formatter) {
+        defmt::write!(f, "{=usize:#x}", self.0)
+    }
+}
@@ -0,0 +1,124 @@
+//! Regression tests for the conclusions in the README.
+//!
+//! Every strategy runs the odd/even `wait_for_signal` tasks of the demos on the simulator,
+//! with the same spawn order and a producer which publishes a new state every 500 ticks.
+//! A change in behaviour of `embassy-sync` or `maitake-sync` shows up as a failing test here.
+
+use waker_churn::sim::{RunOrder, Simulator, TaskStats};
+use waker_churn::{
+    AtomicWakerSignal, ChangeSignal, MultiWakerRegistrationSignal, NaiveSignal, State,
+    WaitQueueSignal, WakerRegistrationSignal, wait_for_signal,
+};
+
+const UPDATES: u32 = 4;
+const PERIOD: u64 = 500;
+
+/// `ThreadModeMutex` only allows access from a thread named `main` when running on std.
+fn in_thread_mode<F: FnOnce() + Send>(f: F) {
+    std::thread::scope(|scope| {
+        std::thread::Builder::new()
+            .name("main".into())
+            .spawn_scoped(scope, f)
+            .expect("Failed to spawn thread")
+            .join()
+            .expect("Test panicked");
+    });
+}
+
+/// Spawn the demo tasks like the `main` of the demos and publish `UPDATES` states.
+fn run_demo<S: ChangeSignal + Default>(tasks: &[(&'static str, bool)]) -> Vec<TaskStats> {
+    let signal = S::default();
+    let mut sim = Simulator::new(RunOrder::Embassy);
+
+    for &(name, odd) in tasks {
+        sim.spawn(name, wait_for_signal(name, &signal, odd));
+    }
+
+    let mut counter = 0;
+    for _ in 0..UPDATES {
+        sim.run_for(PERIOD);
+        counter += 1;
+        signal.set(State::Ready(counter));
+    }
+    sim.run_for(PERIOD);
+
+    sim.tasks()
+}
+
+fn run_two_tasks<S: ChangeSignal + Default>() -> Vec<TaskStats> {
+    run_demo::<S>(&[("TaskTwo", true), ("TaskOne", false)])
+}
+
+/// The task was woken for every update and needed exactly one poll for each (plus the first poll).
+fn assert_woken_every_update(task: &TaskStats) {
+    assert_eq!(task.wakes, UPDATES as u64, "{}", task.name);
+    assert_eq!(task.polls, UPDATES as u64 + 1, "{}", task.name);
+}
+
+/// The task registered its waker once and was never woken again.
+fn assert_starved(task: &TaskStats) {
+    assert_eq!(task.wakes, 0, "{}", task.name);
+    assert_eq!(task.polls, 1, "{}", task.name);
+}
+
+#[test]
+fn naive_only_wakes_last_registered_task() {
+    in_thread_mode(|| {
+        let tasks = run_two_tasks::<NaiveSignal>();
+
+        // TaskOne is spawned last, so it is polled first and TaskTwo overwrites its waker
+        assert_woken_every_update(&tasks[0]);
+        assert_starved(&tasks[1]);
+    });
+}
+
+#[test]
+fn atomic_waker_only_wakes_last_registered_task() {
+    in_thread_mode(|| {
+        let tasks = run_two_tasks::<AtomicWakerSignal>();
+
+        assert_woken_every_update(&tasks[0]);
+        assert_starved(&tasks[1]);
+    });
+}
+
+#[test]
+fn waker_registration_needs_many_polls() {
+    in_thread_mode(|| {
+        let tasks = run_two_tasks::<WakerRegistrationSignal>();
+
+        for task in &tasks {
+            // Both tasks see every update...
+            assert!(task.wakes >= UPDATES as u64, "{}", task.name);
+            // ...but only because they keep waking each other: one poll per tick instead of one per update
+            assert!(task.polls > 10 * UPDATES as u64, "{}", task.name);
+        }
+
+        let polls: u64 = tasks.iter().map(|task| task.polls).sum();
+        assert_eq!(polls, PERIOD * (UPDATES as u64 + 1));
+    });
+}
+
+#[test]
+fn multi_waker_registration_wakes_all_tasks() {
+    in_thread_mode(|| {
+        for task in &run_two_tasks::<MultiWakerRegistrationSignal<2>>() {
+            assert_woken_every_update(task);
+        }
+    });
+}
+
+#[test]
+fn wait_queue_wakes_all_tasks() {
+    in_thread_mode(|| {
+        let tasks = run_demo::<WaitQueueSignal>(&[
+            ("TaskTwo", true),
+            ("TaskOne", false),
+            ("TaskThree", true),
+        ]);
+
+        for task in &tasks {
+            assert_woken_every_update(task);
+        }
+    });
+}